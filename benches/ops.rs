@@ -0,0 +1,123 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ot::composite_op::{self, Side::*, Step::*};
+use ot::single_op;
+
+/// Deterministic xorshift generator, so that runs are comparable.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// A random single-character edit of a document of length `len`.
+fn single_op_for(rng: &mut Rng, len: usize) -> single_op::Op {
+    if len > 0 && rng.below(3) == 0 {
+        single_op::Op::Delete(rng.below(len))
+    } else {
+        single_op::Op::Insert(rng.below(len + 1), 0, b'a' + rng.below(26) as u8)
+    }
+}
+
+/// A random small edit of a document of length `len`, and the document length after it.
+fn composite_op_for(rng: &mut Rng, len: usize) -> (composite_op::Op, usize) {
+    let index = rng.below(len + 1);
+    if index < len && rng.below(3) == 0 {
+        let n = 1 + rng.below((len - index).min(8));
+        (composite_op::Op(vec![Skip(index), Delete(n)]), len - n)
+    } else {
+        let n = 1 + rng.below(8);
+        (
+            composite_op::Op(vec![Skip(index), Insert(vec![b'x'; n])]),
+            len + n,
+        )
+    }
+}
+
+/// `n` consecutive ops starting from a document of length `len`.
+fn consecutive_ops(rng: &mut Rng, mut len: usize, n: usize) -> Vec<composite_op::Op> {
+    (0..n)
+        .map(|_| {
+            let (op, new_len) = composite_op_for(rng, len);
+            len = new_len;
+            op
+        })
+        .collect()
+}
+
+fn apply_single_ops(c: &mut Criterion) {
+    let mut rng = Rng(1);
+    let mut len = 1000;
+    let ops: Vec<_> = (0..10_000)
+        .map(|_| {
+            let op = single_op_for(&mut rng, len);
+            if let single_op::Op::Delete(_) = op {
+                len -= 1;
+            } else {
+                len += 1;
+            }
+            op
+        })
+        .collect();
+    c.bench_function("apply 10k single ops", |b| {
+        b.iter(|| {
+            let mut doc = vec![b'.'; 1000];
+            for op in &ops {
+                single_op::apply(&mut doc, op);
+            }
+            doc
+        })
+    });
+}
+
+fn transform_against_history(c: &mut Criterion) {
+    let mut rng = Rng(2);
+    let history = consecutive_ops(&mut rng, 1000, 1000);
+    let (op, _) = composite_op_for(&mut rng, 1000);
+    c.bench_function("transform against 1k-op history", |b| {
+        b.iter(|| {
+            history
+                .iter()
+                .fold(black_box(op.clone()), |op, concurrent| {
+                    composite_op::transform(&op, concurrent, Right)
+                })
+        })
+    });
+}
+
+fn transform_disjoint_ranged(c: &mut Criterion) {
+    let op1: composite_op::RangedOp = composite_op::Op(vec![Skip(900), Delete(5)]).into();
+    let op2: composite_op::RangedOp = composite_op::Op(
+        (0..100)
+            .flat_map(|_| vec![Skip(3), Insert(b"xy".to_vec()), Skip(2), Delete(1)])
+            .collect(),
+    )
+    .into();
+    c.bench_function("transform disjoint ops without range hints", |b| {
+        b.iter(|| composite_op::transform(black_box(&op1.op), black_box(&op2.op), Left))
+    });
+    c.bench_function("transform disjoint ops with range hints", |b| {
+        b.iter(|| composite_op::transform_ranged(black_box(&op1), black_box(&op2), Left))
+    });
+}
+
+fn compose_burst(c: &mut Criterion) {
+    let mut rng = Rng(3);
+    let burst = consecutive_ops(&mut rng, 1000, 1000);
+    c.bench_function("compose 1k-op burst", |b| {
+        b.iter(|| composite_op::compose_all(black_box(&burst)))
+    });
+}
+
+criterion_group!(
+    benches,
+    apply_single_ops,
+    transform_against_history,
+    transform_disjoint_ranged,
+    compose_burst
+);
+criterion_main!(benches);
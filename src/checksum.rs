@@ -0,0 +1,174 @@
+//! A document checksum that is updated along with each op instead of being recomputed.
+//!
+//! The checksum is a polynomial hash modulo the Mersenne prime 2^61 - 1: a document `d` of
+//! length `n` hashes to the sum of `(d[i] + 1) * BASE^(n - 1 - i)`. The unchanged text after the
+//! bytes an op changes keeps its terms, and the terms of the unchanged text before them are all
+//! multiplied by the same power of `BASE`, so neither has to be hashed term by term. Their sums
+//! still have to be split apart, though, which takes hashing the shorter of the two: an update
+//! costs the size of the edit plus up to half the document, not the size of the edit alone.
+//! That is still cheaper than `checksum` of the whole document, most of all for edits near
+//! either end (typing at the end of the document).
+
+use crate::composite_op::{affected_range, apply_with_scratch, input_len, len_delta, Doc, Step};
+
+const MODULUS: u64 = (1 << 61) - 1;
+const BASE: u64 = 0x0100_0000_01b3;
+
+fn mul(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) % MODULUS as u128) as u64
+}
+
+fn add(a: u64, b: u64) -> u64 {
+    (a + b) % MODULUS
+}
+
+fn sub(a: u64, b: u64) -> u64 {
+    (a + MODULUS - b) % MODULUS
+}
+
+fn pow(mut base: u64, mut exp: u64) -> u64 {
+    let mut result = 1;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+/// `BASE^exp` for a possibly negative `exp`.
+fn base_pow(exp: isize) -> u64 {
+    let power = pow(BASE, exp.abs() as u64);
+    if exp < 0 {
+        // The inverse, by Fermat's little theorem.
+        pow(power, MODULUS - 2)
+    } else {
+        power
+    }
+}
+
+/// The checksum of `doc`, computed from scratch.
+pub fn checksum(doc: &[u8]) -> u64 {
+    doc.iter()
+        .fold(0, |hash, &byte| add(mul(hash, BASE), byte as u64 + 1))
+}
+
+/// A document together with its `checksum`, kept up to date by `apply`.
+#[derive(Debug, Clone)]
+pub struct ChecksummedDoc {
+    bytes: Doc,
+    checksum: u64,
+    scratch: Doc,
+}
+
+impl ChecksummedDoc {
+    pub fn new(bytes: Doc) -> Self {
+        ChecksummedDoc {
+            checksum: checksum(&bytes),
+            bytes,
+            scratch: vec![],
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+
+    /// Applies `op`, updating the checksum by hashing the changed range before and after it,
+    /// and the shorter of the unchanged prefix and suffix.
+    ///
+    /// Panics if `op` goes past the end of the document.
+    pub fn apply(&mut self, op: &[Step]) {
+        assert!(input_len(op) <= self.bytes.len(), "op past end of document");
+        let range = match affected_range(op) {
+            Some(range) => range,
+            None => return,
+        };
+        let old_len = self.bytes.len();
+        let delta = len_delta(op);
+        let new_end = (range.end as isize + delta) as usize;
+        let old_middle = checksum(&self.bytes[range.clone()]);
+        // Weight of the changed range's last byte's successor, the same before and after.
+        let suffix_shift = base_pow((old_len - range.end) as isize);
+        // The terms of the text before the changed range.
+        let prefix_terms = if range.start <= old_len - range.end {
+            mul(
+                checksum(&self.bytes[..range.start]),
+                base_pow((old_len - range.start) as isize),
+            )
+        } else {
+            let suffix = checksum(&self.bytes[range.end..]);
+            sub(self.checksum, add(mul(old_middle, suffix_shift), suffix))
+        };
+        let suffix_terms = sub(
+            self.checksum,
+            add(prefix_terms, mul(old_middle, suffix_shift)),
+        );
+
+        apply_with_scratch(&mut self.bytes, op, &mut self.scratch);
+
+        let new_middle = checksum(&self.bytes[range.start..new_end]);
+        self.checksum = add(
+            add(
+                mul(prefix_terms, base_pow(delta)),
+                mul(new_middle, suffix_shift),
+            ),
+            suffix_terms,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(b""), 0);
+        assert_ne!(checksum(b"ab"), checksum(b"ba"));
+        assert_ne!(checksum(b"\0a"), checksum(b"a"));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut doc = ChecksummedDoc::new(b"hello world".to_vec());
+        doc.apply(&[Skip(5), Delete(6), Insert(b", there".to_vec())]);
+        assert_eq!(doc.bytes(), b"hello, there");
+        assert_eq!(doc.checksum(), checksum(b"hello, there"));
+        doc.apply(&[Skip(12)]);
+        assert_eq!(doc.checksum(), checksum(b"hello, there"));
+    }
+
+    #[test]
+    #[should_panic(expected = "op past end of document")]
+    fn test_apply_past_end() {
+        let mut doc = ChecksummedDoc::new(b"hello".to_vec());
+        doc.apply(&[Skip(100)]);
+    }
+
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn incremental_checksum_matches_from_scratch(
+            initial in any::<Vec<u8>>(),
+            edits in proptest::collection::vec((any::<usize>(), 0..4usize, any::<Vec<u8>>()), 0..20),
+        ) {
+            let mut doc = ChecksummedDoc::new(initial);
+            for (index, delete, insert) in edits {
+                let len = doc.bytes().len();
+                let index = index % (len + 1);
+                let delete = delete.min(len - index);
+                doc.apply(&[Skip(index), Delete(delete), Insert(insert)]);
+                prop_assert_eq!(doc.checksum(), checksum(doc.bytes()));
+            }
+        }
+    }
+}
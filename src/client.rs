@@ -0,0 +1,405 @@
+use crate::composite_op::{
+    affected_range, apply_with_scratch, compose, len_delta, normalize, transform,
+    transform_position, Doc, Gravity, Op, Side::*, Step,
+};
+use crate::envelope::{Envelope, OpId};
+use std::ops::Range;
+
+/// An op composed from consecutive envelopes, remembering the ids of the ops it came from, in
+/// order (e.g. to explain a compacted history entry).
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ComposedOp {
+    pub op: Op,
+    pub provenance: Vec<OpId>,
+}
+
+impl From<Envelope> for ComposedOp {
+    fn from(envelope: Envelope) -> Self {
+        ComposedOp {
+            op: envelope.op,
+            provenance: vec![envelope.id],
+        }
+    }
+}
+
+/// Like `compose`, also concatenating the provenance lists.
+pub fn compose_with_provenance(op1: &ComposedOp, op2: &ComposedOp) -> ComposedOp {
+    ComposedOp {
+        op: compose(&op1.op, &op2.op),
+        provenance: op1
+            .provenance
+            .iter()
+            .chain(&op2.provenance)
+            .copied()
+            .collect(),
+    }
+}
+
+/// Composes a sequence of consecutive envelopes into one op, keeping their ids as its
+/// provenance.
+pub fn compose_envelopes(envelopes: impl IntoIterator<Item = Envelope>) -> ComposedOp {
+    envelopes
+        .into_iter()
+        .fold(ComposedOp::default(), |acc, envelope| {
+            compose_with_provenance(&acc, &envelope.into())
+        })
+}
+
+/// Client side of a collaborative session, talking to a `Server`.
+///
+/// At most one op is in flight at a time: local edits made while waiting for the server's
+/// acknowledgement are buffered and composed into a single op sent after the ack.
+pub struct Client {
+    id: u64,
+    next_seq: u64,
+    doc: Doc,
+    version: usize,
+    /// Sent to the server, not acknowledged yet.
+    pending: Option<Envelope>,
+    /// Local edits made while `pending` was in flight, not sent yet.
+    buffer: Option<Op>,
+    scratch: Doc,
+}
+
+impl Client {
+    /// Creates a client whose document is in sync with server revision `version`. `id` must be
+    /// unique among the clients of the session.
+    pub fn new(id: u64, doc: Doc, version: usize) -> Self {
+        Client {
+            id,
+            next_seq: 0,
+            doc,
+            version,
+            pending: None,
+            buffer: None,
+            scratch: vec![],
+        }
+    }
+
+    /// The document as the user sees it: the last known server state with all local edits
+    /// applied, including ones the server hasn't acknowledged yet.
+    pub fn document(&self) -> &Doc {
+        &self.doc
+    }
+
+    /// The server revision this client has seen.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    fn send(&mut self, op: Op) -> Envelope {
+        let envelope = Envelope {
+            id: OpId {
+                client: self.id,
+                seq: self.next_seq,
+            },
+            op,
+        };
+        self.next_seq += 1;
+        self.pending = Some(envelope.clone());
+        envelope
+    }
+
+    /// Applies a local edit. Returns the op to send to the server, made against `version()`, if
+    /// it should be sent now.
+    pub fn apply_local(&mut self, op: impl Into<Op>) -> Option<Envelope> {
+        let op = op.into();
+        apply_with_scratch(&mut self.doc, &op, &mut self.scratch);
+        if self.pending.is_none() {
+            return Some(self.send(op));
+        }
+        self.buffer = Some(match self.buffer.take() {
+            Some(buffer) => compose(&buffer, &op),
+            None => op,
+        });
+        None
+    }
+
+    /// Handles the server's acknowledgement of the pending op. Returns the buffered op to send
+    /// next, if any.
+    pub fn ack(&mut self) -> Option<Envelope> {
+        self.version += 1;
+        self.pending = None;
+        let buffer = self.buffer.take()?;
+        Some(self.send(buffer))
+    }
+
+    /// Applies an op broadcast by the server. If it's this client's pending op coming back, it's
+    /// already applied, so it's handled as an ack instead and the buffered op to send next is
+    /// returned, if any.
+    pub fn apply_server(&mut self, envelope: Envelope) -> Option<Envelope> {
+        self.apply_server_rebased(envelope).0
+    }
+
+    /// Like `apply_server`, also returning where the local edits that the op moved or changed
+    /// (i.e. that got rebased onto it) are in the new document, one range per not yet
+    /// acknowledged op, from its first change to the end of its last (e.g. for highlighting
+    /// them).
+    pub fn apply_server_rebased(
+        &mut self,
+        envelope: Envelope,
+    ) -> (Option<Envelope>, Vec<Range<usize>>) {
+        if self.pending.as_ref().map(|pending| pending.id) == Some(envelope.id) {
+            return (self.ack(), vec![]);
+        }
+        self.version += 1;
+        (None, self.apply_remote(envelope.op))
+    }
+
+    /// Catches up after missing server broadcasts (e.g. while disconnected): applies
+    /// `server_ops`, the ops the server applied since `version()`, bringing the client to
+    /// `server_version`. The local edits are rebased onto them and stay visible.
+    ///
+    /// The pending op may be among `server_ops` (when only its ack was lost), in which case it's
+    /// handled as an ack like in `apply_server`. Returns the op to send next, if any: the pending
+    /// op if it never reached the server, now made against `server_version`, or else the
+    /// buffered one.
+    pub fn rebase_onto(
+        &mut self,
+        server_ops: &[Envelope],
+        server_version: usize,
+    ) -> Option<Envelope> {
+        debug_assert_eq!(self.version + server_ops.len(), server_version);
+        for envelope in server_ops {
+            if self.pending.as_ref().map(|pending| pending.id) == Some(envelope.id) {
+                self.ack();
+            } else {
+                self.version += 1;
+                self.apply_remote(envelope.op.clone());
+            }
+        }
+        self.pending.clone()
+    }
+
+    /// Rebases the local edits onto `op`, an op the server applied after them, and applies `op`
+    /// on top of them. Returns the ranges as described in `apply_server_rebased`.
+    fn apply_remote(&mut self, mut op: Op) -> Vec<Range<usize>> {
+        let mut rebased = vec![];
+        let pending = self.pending.iter_mut().map(|pending| &mut pending.op);
+        for local in pending.chain(self.buffer.iter_mut()) {
+            // The server already ordered `op` before our local ops.
+            let transformed_local = transform(local, &op, Right);
+            op = transform(&op, local, Left);
+            // Ranges of earlier local ops move along with this one.
+            for range in &mut rebased {
+                *range = transform_output_range(range, &transformed_local);
+            }
+            if transformed_local != normalize(local) {
+                rebased.extend(output_range(&transformed_local));
+            }
+            *local = transformed_local;
+        }
+        apply_with_scratch(&mut self.doc, &op, &mut self.scratch);
+        rebased
+    }
+}
+
+/// Where the changes of `op` are in the document it produces.
+fn output_range(op: &[Step]) -> Option<Range<usize>> {
+    let range = affected_range(op)?;
+    Some(range.start..(range.end as isize + len_delta(op)) as usize)
+}
+
+/// Where `range` ends up once `op` is applied, growing with text inserted at its ends.
+fn transform_output_range(range: &Range<usize>, op: &[Step]) -> Range<usize> {
+    transform_position(range.start, op, Gravity::Before)
+        ..transform_position(range.end, op, Gravity::After)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::{Step, Step::*};
+    use crate::server::Server;
+
+    fn envelope(client: u64, seq: u64, op: Vec<Step>) -> Envelope {
+        Envelope {
+            id: OpId { client, seq },
+            op: Op(op),
+        }
+    }
+
+    #[test]
+    fn test_compose_envelopes() {
+        let composed = compose_envelopes(vec![
+            envelope(1, 17, vec![Insert(b"ab".to_vec())]),
+            envelope(1, 18, vec![Skip(2), Insert(b"c".to_vec())]),
+            envelope(2, 19, vec![Delete(1)]),
+        ]);
+        assert_eq!(composed.op, vec![Insert(b"bc".to_vec())]);
+        assert_eq!(
+            composed.provenance,
+            vec![
+                OpId { client: 1, seq: 17 },
+                OpId { client: 1, seq: 18 },
+                OpId { client: 2, seq: 19 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_local_edits_visible_before_ack() {
+        let mut client = Client::new(7, b"abc".to_vec(), 0);
+        let sent = client.apply_local(vec![Skip(1), Insert(b"x".to_vec())]);
+        assert_eq!(
+            sent,
+            Some(envelope(7, 0, vec![Skip(1), Insert(b"x".to_vec())]))
+        );
+        assert_eq!(client.document(), b"axbc");
+
+        // Buffered while the first op is in flight, but still visible.
+        assert_eq!(
+            client.apply_local(vec![Skip(4), Insert(b"y".to_vec())]),
+            None
+        );
+        assert_eq!(client.document(), b"axbcy");
+        assert_eq!(client.version(), 0);
+
+        assert_eq!(
+            client.ack(),
+            Some(envelope(7, 1, vec![Skip(4), Insert(b"y".to_vec())]))
+        );
+        assert_eq!(client.version(), 1);
+        assert_eq!(client.ack(), None);
+    }
+
+    #[test]
+    fn test_apply_server_rebased() {
+        let mut client = Client::new(1, b"abc".to_vec(), 0);
+        client.apply_local(vec![Skip(1), Insert(b"x".to_vec())]);
+
+        let (sent, rebased) =
+            client.apply_server_rebased(envelope(2, 0, vec![Insert(b"yy".to_vec())]));
+        assert_eq!(sent, None);
+        assert_eq!(client.document(), b"yyaxbc");
+        assert_eq!(rebased, vec![3..4]);
+
+        // After the local edit, so it isn't rebased.
+        let (_, rebased) =
+            client.apply_server_rebased(envelope(2, 1, vec![Skip(4), Insert(b"z".to_vec())]));
+        assert_eq!(client.document(), b"yyaxbzc");
+        assert_eq!(rebased, vec![]);
+
+        // Both the pending and the buffered edit, in the new document.
+        client.apply_local(vec![Skip(7), Insert(b"w".to_vec())]);
+        let (_, rebased) = client.apply_server_rebased(envelope(2, 2, vec![Insert(b"v".to_vec())]));
+        assert_eq!(client.document(), b"vyyaxbzcw");
+        assert_eq!(rebased, vec![4..5, 8..9]);
+    }
+
+    #[test]
+    fn test_rebase_onto_missed_ops() {
+        let mut server = Server::new(b"abc".to_vec());
+        let mut client = Client::new(1, b"abc".to_vec(), 0);
+        // Sent just before the connection dropped, never reached the server.
+        let lost = client
+            .apply_local(vec![Skip(1), Insert(b"x".to_vec())])
+            .unwrap();
+        client.apply_local(vec![Skip(3), Delete(1)]);
+
+        let missed = vec![
+            server
+                .receive_envelope(0, envelope(2, 0, vec![Insert(b"yy".to_vec())]))
+                .unwrap(),
+            server
+                .receive_envelope(1, envelope(2, 1, vec![Skip(3), Delete(1)]))
+                .unwrap(),
+        ];
+
+        let resent = client.rebase_onto(&missed, 2).unwrap();
+        assert_eq!(resent.id, lost.id);
+        assert_eq!(resent.op, vec![Skip(3), Insert(b"x".to_vec())]);
+        assert_eq!(client.version(), 2);
+        assert_eq!(client.document(), b"yyax");
+
+        let broadcast = server.receive_envelope(2, resent).unwrap();
+        let buffered = client.apply_server(broadcast).unwrap();
+        let broadcast = server.receive_envelope(3, buffered).unwrap();
+        assert_eq!(client.apply_server(broadcast), None);
+        assert_eq!(server.document(), client.document());
+        assert_eq!(client.version(), 4);
+    }
+
+    #[test]
+    fn test_rebase_onto_with_lost_ack() {
+        let mut server = Server::new(b"abc".to_vec());
+        let mut client = Client::new(1, b"abc".to_vec(), 0);
+        let sent = client
+            .apply_local(vec![Skip(1), Insert(b"x".to_vec())])
+            .unwrap();
+        client.apply_local(vec![Skip(4), Insert(b"z".to_vec())]);
+
+        // The server got the op, but the connection dropped before its broadcast came back.
+        let missed = vec![
+            server.receive_envelope(0, sent.clone()).unwrap(),
+            server
+                .receive_envelope(0, envelope(2, 0, vec![Insert(b"y".to_vec())]))
+                .unwrap(),
+        ];
+
+        // The buffered op is sent next, not the acknowledged one again.
+        let next = client.rebase_onto(&missed, 2).unwrap();
+        assert_ne!(next.id, sent.id);
+        assert_eq!(client.version(), 2);
+        assert_eq!(client.document(), b"yaxbcz");
+
+        let broadcast = server.receive_envelope(2, next).unwrap();
+        assert_eq!(client.apply_server(broadcast), None);
+        assert_eq!(server.document(), client.document());
+        assert_eq!(client.version(), 3);
+    }
+
+    #[test]
+    fn test_own_op_broadcast_is_an_ack() {
+        let mut server = Server::new(b"abc".to_vec());
+        let mut client = Client::new(1, b"abc".to_vec(), 0);
+        let sent = client.apply_local(vec![Insert(b"x".to_vec())]).unwrap();
+        client.apply_local(vec![Skip(1), Delete(1)]);
+
+        let other = server
+            .receive_envelope(0, envelope(2, 0, vec![Insert(b"y".to_vec())]))
+            .unwrap();
+        // Transformed by the server, but still recognizable.
+        let own = server.receive_envelope(0, sent.clone()).unwrap();
+        assert_eq!(own.id, sent.id);
+        assert_ne!(own.op, sent.op);
+
+        assert_eq!(client.apply_server(other), None);
+        assert_eq!(client.document(), b"yxbc");
+        assert_eq!(
+            client.apply_server(own),
+            Some(envelope(1, 1, vec![Skip(2), Delete(1)]))
+        );
+        assert_eq!(client.document(), b"yxbc");
+        assert_eq!(client.version(), 2);
+    }
+
+    #[test]
+    fn test_two_clients_converge() {
+        let mut server = Server::new(b"abc".to_vec());
+        let mut alice = Client::new(1, b"abc".to_vec(), 0);
+        let mut bob = Client::new(2, b"abc".to_vec(), 0);
+
+        let alice_op = alice.apply_local(vec![Insert(b"A".to_vec())]).unwrap();
+        let bob_op = bob.apply_local(vec![Skip(1), Delete(1)]).unwrap();
+        bob.apply_local(vec![Skip(2), Insert(b"B".to_vec())]);
+
+        let broadcast = server.receive_envelope(alice.version(), alice_op).unwrap();
+        assert_eq!(alice.apply_server(broadcast.clone()), None);
+        assert_eq!(bob.apply_server(broadcast), None);
+
+        let broadcast = server.receive_envelope(0, bob_op).unwrap();
+        let bob_op = bob.apply_server(broadcast.clone()).unwrap();
+        assert_eq!(alice.apply_server(broadcast), None);
+
+        let broadcast = server.receive_envelope(bob.version(), bob_op).unwrap();
+        assert_eq!(bob.apply_server(broadcast.clone()), None);
+        assert_eq!(alice.apply_server(broadcast), None);
+
+        assert_eq!(server.document(), b"AacB");
+        assert_eq!(alice.document(), server.document());
+        assert_eq!(bob.document(), server.document());
+        assert_eq!(alice.version(), 3);
+        assert_eq!(bob.version(), 3);
+    }
+}
@@ -0,0 +1,259 @@
+//! Compact binary encoding of composite ops (and of rich-text ops, see `encode_rich_text`).
+//!
+//! Each step is a tag byte followed by a LEB128 varint: the length for `Skip` and `Delete`, the
+//! chunk length (followed by the chunk itself) for `Insert`.
+
+use crate::composite_op::{normalize, Op, Step, Step::*};
+use crate::rich_text;
+
+const TAG_SKIP: u8 = 0;
+const TAG_INSERT: u8 = 1;
+const TAG_DELETE: u8 = 2;
+const TAG_FORMAT: u8 = 3;
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum DecodeError {
+    /// The input ended in the middle of a step.
+    UnexpectedEof,
+    InvalidTag(u8),
+    /// A length doesn't fit in `usize`.
+    Overflow,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<usize, DecodeError> {
+    let mut result: usize = 0;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first().ok_or(DecodeError::UnexpectedEof)?;
+        *input = rest;
+        let bits = (byte & 0x7f) as usize;
+        if shift >= usize::max_value().count_ones() || (bits << shift) >> shift != bits {
+            return Err(DecodeError::Overflow);
+        }
+        result |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+pub fn encode(op: &[Step]) -> Vec<u8> {
+    let mut out = vec![];
+    for step in op {
+        match step {
+            Skip(n) => {
+                out.push(TAG_SKIP);
+                write_varint(&mut out, *n);
+            }
+            Insert(s) => {
+                out.push(TAG_INSERT);
+                write_varint(&mut out, s.len());
+                out.extend_from_slice(s);
+            }
+            Delete(n) => {
+                out.push(TAG_DELETE);
+                write_varint(&mut out, *n);
+            }
+        }
+    }
+    out
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Encodes a rich-text op like `encode` does composite ops. A `Retain` without attribute changes
+/// is encoded as a skip, one with changes as `TAG_FORMAT`, the length, the number of changes and
+/// each key followed by 0 for a removal or by 1 and the value. Keys and values are encoded as
+/// their length and bytes.
+///
+/// Attributes iterate in key order, so equal ops always encode to the same bytes.
+pub fn encode_rich_text(op: &[rich_text::Step]) -> Vec<u8> {
+    let mut out = vec![];
+    for step in op {
+        match step {
+            rich_text::Step::Retain(n, None) => {
+                out.push(TAG_SKIP);
+                write_varint(&mut out, *n);
+            }
+            rich_text::Step::Retain(n, Some(attributes)) => {
+                out.push(TAG_FORMAT);
+                write_varint(&mut out, *n);
+                write_varint(&mut out, attributes.len());
+                for (key, value) in attributes {
+                    write_bytes(&mut out, key.as_bytes());
+                    match value {
+                        Some(value) => {
+                            out.push(1);
+                            write_bytes(&mut out, value.as_bytes());
+                        }
+                        None => out.push(0),
+                    }
+                }
+            }
+            rich_text::Step::Insert(s) => {
+                out.push(TAG_INSERT);
+                write_bytes(&mut out, s);
+            }
+            rich_text::Step::Delete(n) => {
+                out.push(TAG_DELETE);
+                write_varint(&mut out, *n);
+            }
+        }
+    }
+    out
+}
+
+/// A canonical encoding of `op`, for hashing or signing: equivalent ops (ones that normalize to
+/// the same op) get the same bytes.
+///
+/// `encode` is deterministic, so this holds as long as `normalize` maps equivalent ops to the
+/// same op, which it does by merging adjacent steps, ordering inserts before deletes and dropping
+/// empty steps and the trailing skip.
+pub fn canonical_bytes(op: &[Step]) -> Vec<u8> {
+    encode(&normalize(op))
+}
+
+/// Decodes an op produced by `encode`. Never panics on malformed input.
+pub fn decode(input: &[u8]) -> Result<Op, DecodeError> {
+    let mut input = input;
+    let mut op = vec![];
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        let n = read_varint(&mut input)?;
+        let step = match tag {
+            TAG_SKIP => Skip(n),
+            TAG_INSERT => {
+                if n > input.len() {
+                    return Err(DecodeError::UnexpectedEof);
+                }
+                let (chunk, rest) = input.split_at(n);
+                input = rest;
+                Insert(chunk.to_vec())
+            }
+            TAG_DELETE => Delete(n),
+            _ => return Err(DecodeError::InvalidTag(tag)),
+        };
+        op.push(step);
+    }
+    Ok(Op(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            encode(&[Skip(300), Insert(b"ab".to_vec()), Delete(1)]),
+            vec![TAG_SKIP, 0xac, 0x02, TAG_INSERT, 2, b'a', b'b', TAG_DELETE, 1]
+        );
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode(&[TAG_SKIP]), Err(DecodeError::UnexpectedEof));
+        assert_eq!(decode(&[TAG_SKIP, 0x80]), Err(DecodeError::UnexpectedEof));
+        assert_eq!(
+            decode(&[TAG_INSERT, 3, b'a']),
+            Err(DecodeError::UnexpectedEof)
+        );
+        assert_eq!(decode(&[7, 0]), Err(DecodeError::InvalidTag(7)));
+        assert_eq!(
+            decode(&[TAG_DELETE, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f]),
+            Err(DecodeError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_canonical_bytes() {
+        let op1 = vec![
+            Skip(1),
+            Skip(2),
+            Delete(1),
+            Insert(b"ab".to_vec()),
+            Delete(2),
+        ];
+        let op2 = vec![
+            Skip(3),
+            Insert(b"a".to_vec()),
+            Insert(b"b".to_vec()),
+            Delete(3),
+            Skip(4),
+        ];
+        assert_ne!(encode(&op1), encode(&op2));
+        assert_eq!(canonical_bytes(&op1), canonical_bytes(&op2));
+        assert_ne!(
+            canonical_bytes(&op1),
+            canonical_bytes(&[Skip(3), Delete(3)])
+        );
+    }
+
+    #[test]
+    fn test_encode_rich_text_is_independent_of_insertion_order() {
+        let mut attributes1 = rich_text::Attributes::new();
+        attributes1.insert("italic".to_string(), None);
+        attributes1.insert("bold".to_string(), Some("true".to_string()));
+        attributes1.insert("color".to_string(), Some("red".to_string()));
+        let mut attributes2 = rich_text::Attributes::new();
+        attributes2.insert("color".to_string(), Some("red".to_string()));
+        attributes2.insert("bold".to_string(), Some("true".to_string()));
+        attributes2.insert("italic".to_string(), None);
+
+        let op = |attributes| vec![rich_text::Step::Retain(2, Some(attributes))];
+        let encoded = encode_rich_text(&op(attributes1));
+        assert_eq!(encoded, encode_rich_text(&op(attributes2)));
+        assert_eq!(
+            encoded,
+            [
+                &[TAG_FORMAT, 2, 3][..],
+                &[4],
+                b"bold",
+                &[1, 4],
+                b"true",
+                &[5],
+                b"color",
+                &[1, 3],
+                b"red",
+                &[6],
+                b"italic",
+                &[0],
+            ]
+            .concat()
+        );
+    }
+
+    use proptest::prelude::*;
+
+    fn arb_step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            any::<usize>().prop_map(Skip),
+            any::<Vec<u8>>().prop_map(Insert),
+            any::<usize>().prop_map(Delete),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn roundtrip(op in proptest::collection::vec(arb_step(), 0..10)) {
+            prop_assert_eq!(decode(&encode(&op)), Ok(Op(op)));
+        }
+
+        #[test]
+        fn decode_arbitrary_bytes_doesnt_panic(input in any::<Vec<u8>>()) {
+            let _ = decode(&input);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 #![allow(warnings)]
 
+use crate::doc_state::DocState;
+use std::borrow::Cow;
+use std::cmp::min;
 use std::cmp::Ordering::*;
+use std::ops::Range;
 
 pub type Doc = Vec<u8>;
 
@@ -14,9 +18,116 @@ pub enum Step {
 }
 use Step::*;
 
-type Op = Vec<Step>;
+impl Step {
+    /// An `Insert` of `&str`, `String`, `&[u8]` or `Vec<u8>` (which is used without copying).
+    pub fn insert(bytes: impl Into<Chunk>) -> Step {
+        Insert(bytes.into())
+    }
+}
+
+/// A composite operation: a sequence of steps, followed by an implicit skip over the rest of
+/// the document.
+///
+/// Derefs to `[Step]`, so it can be used like a slice of its steps.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Op(pub Vec<Step>);
+
+impl Op {
+    /// The op that changes nothing. `transform`, `compose` and `apply` treat any op for which
+    /// `is_identity` holds (e.g. `[Skip(0)]`) the same way, but this is the canonical spelling.
+    pub const fn identity() -> Op {
+        Op(Vec::new())
+    }
+
+    /// The number of steps (not the length of a document the op applies to, see `input_len`).
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Op {
+    /// See `classify`.
+    pub fn classify(&self) -> OpKind {
+        classify(self)
+    }
+}
+
+impl std::ops::Deref for Op {
+    type Target = [Step];
+
+    fn deref(&self) -> &[Step] {
+        &self.0
+    }
+}
+
+impl From<Vec<Step>> for Op {
+    fn from(steps: Vec<Step>) -> Op {
+        Op(steps)
+    }
+}
+
+impl std::iter::FromIterator<Step> for Op {
+    fn from_iter<I: IntoIterator<Item = Step>>(iter: I) -> Op {
+        Op(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for Op {
+    type Item = Step;
+    type IntoIter = std::vec::IntoIter<Step>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Op {
+    type Item = &'a Step;
+    type IntoIter = std::slice::Iter<'a, Step>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl PartialEq<Vec<Step>> for Op {
+    fn eq(&self, other: &Vec<Step>) -> bool {
+        self.0 == *other
+    }
+}
+
+/// A text container ops can be applied to, so that editors can keep their own representation
+/// (a gap buffer, a rope, ...).
+pub trait TextBuffer {
+    fn insert_at(&mut self, index: usize, bytes: &[u8]);
+    fn delete_range(&mut self, range: Range<usize>);
+    fn len(&self) -> usize;
+}
+
+impl TextBuffer for Vec<u8> {
+    fn insert_at(&mut self, index: usize, bytes: &[u8]) {
+        let old_len = self.len();
+        self.resize(old_len + bytes.len(), 0);
+        self.copy_within(index..old_len, index + bytes.len());
+        self[index..(index + bytes.len())].copy_from_slice(bytes);
+    }
+
+    fn delete_range(&mut self, range: Range<usize>) {
+        self.drain(range);
+    }
 
-pub fn apply(doc: &mut Doc, op: &[Step]) {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+}
+
+/// Applies `op` to `doc` in place. Panics if the op reaches past the end of `doc`, see
+/// `try_apply`.
+pub fn apply<B: TextBuffer + ?Sized>(doc: &mut B, op: &[Step]) {
     let mut index = 0;
     for step in op {
         match step {
@@ -24,21 +135,389 @@ pub fn apply(doc: &mut Doc, op: &[Step]) {
                 index += n;
             }
             Insert(s) => {
-                let old_doc_len = doc.len();
-                doc.resize(old_doc_len + s.len(), 0);
-                doc.copy_within(index..old_doc_len, index + s.len());
-                doc[index..(index + s.len())].copy_from_slice(&s);
+                doc.insert_at(index, s);
                 index += s.len()
             }
             Delete(n) => {
-                doc.drain(index..(index + n));
+                // The following text moves back to `index`.
+                doc.delete_range(index..(index + n));
+            }
+        }
+    }
+}
+
+/// Where `apply_budgeted` stopped applying an op, to resume from. Start with
+/// `ApplyCursor::default()`.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ApplyCursor {
+    /// The step being applied.
+    step: usize,
+    /// How much of the step has been applied.
+    offset: usize,
+    /// Position in the document.
+    index: usize,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ApplyProgress {
+    Done,
+    MoreRemaining,
+}
+
+/// Applies `op` to `doc` in chunks (e.g. a huge op spread over several frames), resuming from
+/// `cursor` and inserting or deleting at most `budget` bytes per call. Skips are free. Call it
+/// with the same `op` and `cursor` until it returns `Done`; in between, `doc` is partially
+/// updated.
+///
+/// Panics if `budget` is 0, since no call could then make progress on an insert or delete.
+pub fn apply_budgeted<B: TextBuffer + ?Sized>(
+    doc: &mut B,
+    op: &[Step],
+    cursor: &mut ApplyCursor,
+    mut budget: usize,
+) -> ApplyProgress {
+    assert!(budget > 0, "budget must be positive");
+    while let Some(step) = op.get(cursor.step) {
+        let left = step_len(step) - cursor.offset;
+        let n = match step {
+            Skip(_) => left,
+            _ => min(left, budget),
+        };
+        if n == 0 && left > 0 {
+            return ApplyProgress::MoreRemaining;
+        }
+        match step {
+            Skip(_) => cursor.index += n,
+            Insert(s) => {
+                doc.insert_at(cursor.index, &s[cursor.offset..(cursor.offset + n)]);
+                cursor.index += n;
+                budget -= n;
+            }
+            Delete(_) => {
+                doc.delete_range(cursor.index..(cursor.index + n));
+                budget -= n;
+            }
+        }
+        cursor.offset += n;
+        if cursor.offset == step_len(step) {
+            cursor.step += 1;
+            cursor.offset = 0;
+        }
+    }
+    ApplyProgress::Done
+}
+
+/// Like `apply`, but builds the new document into `scratch` in a single forward pass and then
+/// swaps it into `doc`, instead of shifting `doc` in place for every step.
+///
+/// After the call `scratch` holds the old document, so its allocation can be reused (unless the
+/// op is an identity, which leaves both untouched).
+pub fn apply_with_scratch(doc: &mut Doc, op: &[Step], scratch: &mut Doc) {
+    if is_identity(op) {
+        return;
+    }
+    apply_into(doc, op, scratch);
+    std::mem::swap(doc, scratch);
+}
+
+/// Writes the result of applying `op` to `src` into `dst`, replacing its contents. `dst` is
+/// grown at most once, to exactly the length of the result, and `src` isn't shifted around as
+/// `apply` does.
+pub fn apply_into(src: &[u8], op: &[Step], dst: &mut Doc) {
+    dst.clear();
+    dst.reserve_exact((src.len() as isize + len_delta(op)).max(0) as usize);
+    let mut index = 0;
+    for step in op {
+        match step {
+            Skip(n) => {
+                dst.extend_from_slice(&src[index..(index + n)]);
+                index += n;
+            }
+            Insert(s) => {
+                dst.extend_from_slice(s);
+            }
+            Delete(n) => {
+                index += n;
+            }
+        }
+    }
+    dst.extend_from_slice(&src[index..]);
+}
+
+/// The length of the document prefix `op` skips over or deletes. The op can be applied to
+/// documents at least this long (the rest is covered by the implicit trailing skip).
+///
+/// Saturates at `usize::MAX`, which no document is long enough for.
+pub fn input_len(op: &[Step]) -> usize {
+    op.iter()
+        .map(|step| match step {
+            Skip(n) | Delete(n) => *n,
+            Insert(_) => 0,
+        })
+        .fold(0, usize::saturating_add)
+}
+
+/// The change in document length applying `op` produces: inserted minus deleted bytes.
+pub fn len_delta(op: &[Step]) -> isize {
+    op.iter()
+        .map(|step| match step {
+            Skip(_) => 0,
+            Insert(s) => s.len() as isize,
+            Delete(n) => -(*n as isize),
+        })
+        .sum()
+}
+
+/// Like `apply`, but fails instead of panicking if the op reaches past the end of `doc`. The
+/// whole op is checked before anything is applied, so on failure `doc` is left unchanged.
+pub fn try_apply(doc: &mut Doc, op: &[Step]) -> Result<(), ApplyError> {
+    let input_len = input_len(op);
+    if input_len > doc.len() {
+        return Err(ApplyError::OutOfBounds {
+            input_len,
+            doc_len: doc.len(),
+        });
+    }
+    apply_with_scratch(doc, op, &mut vec![]);
+    Ok(())
+}
+
+/// Like `try_apply`, but also rejects ops changing any of the `readonly` ranges (e.g. labels in a
+/// form): deleting a byte in one of them or inserting strictly inside one. Inserting right before
+/// or after a read-only range is allowed.
+pub fn try_apply_with_readonly(
+    doc: &mut Doc,
+    op: &[Step],
+    readonly: &[Range<usize>],
+) -> Result<(), ApplyError> {
+    let mut pos: usize = 0;
+    for step in op {
+        match step {
+            Skip(n) => pos = pos.saturating_add(*n),
+            Insert(s) => {
+                if readonly
+                    .iter()
+                    .any(|range| !s.is_empty() && range.start < pos && pos < range.end)
+                {
+                    return Err(ApplyError::ReadOnly(pos));
+                }
+            }
+            Delete(n) => {
+                let end = pos.saturating_add(*n);
+                if let Some(range) = readonly
+                    .iter()
+                    .find(|range| range.start < end && pos < range.end)
+                {
+                    return Err(ApplyError::ReadOnly(pos.max(range.start)));
+                }
+                pos = end;
+            }
+        }
+    }
+    try_apply(doc, op)
+}
+
+/// An op tagged with the document version it was made against.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct VersionedOp {
+    pub base: usize,
+    pub op: Op,
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum ApplyError {
+    /// The op was made against version `base`, but the document is at version `current`.
+    VersionMismatch { base: usize, current: usize },
+    /// The op skips or deletes `input_len` bytes, more than the `doc_len` in the document.
+    OutOfBounds { input_len: usize, doc_len: usize },
+    /// The op inserts or deletes at `pos`, inside a read-only range.
+    ReadOnly(usize),
+}
+
+/// Applies `vop` to `doc`, which is at `current_version`, and returns the new version. Fails
+/// without touching `doc` if the op was made against a different version.
+pub fn apply_versioned(
+    doc: &mut Doc,
+    current_version: usize,
+    vop: &VersionedOp,
+) -> Result<usize, ApplyError> {
+    if vop.base != current_version {
+        return Err(ApplyError::VersionMismatch {
+            base: vop.base,
+            current: current_version,
+        });
+    }
+    apply_with_scratch(doc, &vop.op, &mut vec![]);
+    Ok(current_version + 1)
+}
+
+/// Applies `op` to the part of `buffer` starting at `base_offset`, in place: op positions are
+/// relative to `base_offset`, and the bytes before it are left alone. Useful for editing a view
+/// into a larger buffer, e.g. a single line of a file.
+///
+/// Fails without touching `buffer` if the op reaches past its end.
+pub fn apply_at(buffer: &mut Doc, base_offset: usize, op: &[Step]) -> Result<(), ApplyError> {
+    let doc_len = buffer.len().saturating_sub(base_offset);
+    let input_len = input_len(op);
+    if base_offset > buffer.len() || input_len > doc_len {
+        return Err(ApplyError::OutOfBounds { input_len, doc_len });
+    }
+    let mut index = base_offset;
+    for step in op {
+        match step {
+            Skip(n) => index += n,
+            Insert(s) => {
+                buffer.splice(index..index, s.iter().cloned());
+                index += s.len();
+            }
+            Delete(n) => {
+                buffer.drain(index..(index + n));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A contiguous edit made by `apply_changes`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Change {
+    /// Position of the edit in the resulting document.
+    pub pos: usize,
+    /// Bytes removed from the original document at this position.
+    pub removed: Vec<u8>,
+    /// Bytes inserted at this position.
+    pub inserted: Vec<u8>,
+}
+
+/// Like `apply`, but also returns the changes made, in document order. Adjacent inserts and
+/// deletes (with no `Skip` in between) are reported as a single `Change`.
+pub fn apply_changes(doc: &mut Doc, op: &[Step]) -> Vec<Change> {
+    apply_tagged(doc, op, |_| None)
+        .into_iter()
+        .map(|change| change.change)
+        .collect()
+}
+
+/// A step of a `TaggedOp`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum TaggedStep {
+    Skip(usize),
+    /// Inserts the bytes, tagged with where they came from.
+    Insert(Chunk, Option<u64>),
+    Delete(usize),
+}
+
+/// An op whose inserts are tagged with their provenance (e.g. the id of the user who typed
+/// them), so that the UI can highlight text other people just inserted. `transform_tagged` keeps
+/// each insert's tag with it, and `apply_observed` reports the tags.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct TaggedOp(pub Vec<TaggedStep>);
+
+impl TaggedOp {
+    /// `op` with all its inserts tagged with `tag`.
+    pub fn new(op: &[Step], tag: Option<u64>) -> Self {
+        TaggedOp(
+            op.iter()
+                .map(|step| match step {
+                    Skip(n) => TaggedStep::Skip(*n),
+                    Insert(s) => TaggedStep::Insert(s.clone(), tag),
+                    Delete(n) => TaggedStep::Delete(*n),
+                })
+                .collect(),
+        )
+    }
+
+    /// The op without the tags.
+    pub fn to_op(&self) -> Op {
+        self.0
+            .iter()
+            .map(|step| match step {
+                TaggedStep::Skip(n) => Skip(*n),
+                TaggedStep::Insert(s, _) => Insert(s.clone()),
+                TaggedStep::Delete(n) => Delete(*n),
+            })
+            .collect()
+    }
+}
+
+/// A `Change` made by `apply_observed`, with the tag of the bytes it inserted (`None` if it
+/// only removes bytes).
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TaggedChange {
+    pub change: Change,
+    pub tag: Option<u64>,
+}
+
+/// Like `apply_changes`, for a tagged op. Adjacent inserts with different tags are reported as
+/// separate changes.
+pub fn apply_observed(doc: &mut Doc, op: &TaggedOp) -> Vec<TaggedChange> {
+    apply_tagged(doc, &op.0, |step| match step {
+        TaggedStep::Insert(_, tag) => *tag,
+        _ => None,
+    })
+}
+
+/// `apply_observed` for an op in any representation, `tag` giving the tags of its inserts.
+fn apply_tagged<S: TransformStep>(
+    doc: &mut Doc,
+    op: &[S],
+    tag: impl Fn(&S) -> Option<u64>,
+) -> Vec<TaggedChange> {
+    if op.iter().all(|step| matches!(step.shape(), Shape::Skip(_))) {
+        return vec![];
+    }
+    let mut changes: Vec<TaggedChange> = vec![];
+    let mut result = Vec::with_capacity(doc.len());
+    let mut index = 0;
+    let mut in_edit = false;
+    for step in op {
+        let shape = step.shape();
+        if let Shape::Skip(n) = shape {
+            result.extend_from_slice(&doc[index..(index + n)]);
+            index += n;
+            in_edit = false;
+            continue;
+        }
+        let starts_edit = match (changes.last(), shape) {
+            _ if !in_edit => true,
+            (Some(last), Shape::Insert(_)) => {
+                !last.change.inserted.is_empty() && last.tag != tag(step)
+            }
+            _ => false,
+        };
+        if starts_edit {
+            changes.push(TaggedChange {
+                change: Change {
+                    pos: result.len(),
+                    removed: vec![],
+                    inserted: vec![],
+                },
+                tag: None,
+            });
+            in_edit = true;
+        }
+        let last = changes.last_mut().unwrap();
+        match shape {
+            Shape::Insert(_) => {
+                result.extend_from_slice(step.chunk());
+                last.change.inserted.extend_from_slice(step.chunk());
+                last.tag = tag(step);
+            }
+            Shape::Delete(n) => {
+                last.change
+                    .removed
+                    .extend_from_slice(&doc[index..(index + n)]);
                 index += n;
             }
+            Shape::Skip(_) => unreachable!(),
         }
     }
+    result.extend_from_slice(&doc[index..]);
+    *doc = result;
+    changes
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Side {
     Left,
     Right,
@@ -46,27 +525,2845 @@ pub enum Side {
 
 use Side::*;
 
+/// Orders two concurrent inserts, at `pos1` by `op1` and at `pos2` by `op2`, in the transformed
+/// document: `Less` if `op1`'s insert ends up in front of `op2`'s, `Greater` if behind it.
+///
+/// Inserts at different positions keep their order; at the same position `op1`'s insert goes
+/// first if `side` is `Left`. Never returns `Equal`. The transforms of `composite_op`,
+/// `single_op`, `rich_text` and `grid` all order concurrent inserts with it, so custom
+/// transforms can stay consistent with them by calling it too.
+pub fn insert_tiebreak<P: Ord>(pos1: P, pos2: P, side: Side) -> std::cmp::Ordering {
+    match (pos1.cmp(&pos2), side) {
+        (Equal, Left) => Less,
+        (Equal, Right) => Greater,
+        (ordering, _) => ordering,
+    }
+}
+
+fn step_len(step: &Step) -> usize {
+    match step {
+        Skip(n) | Delete(n) => *n,
+        Insert(s) => s.len(),
+    }
+}
+
+static INFINITE_SKIP: Step = Skip(usize::MAX);
+
+/// Walks the steps of an op, allowing them to be consumed partially.
+///
+/// Past the end of the op it yields an infinite `Skip`, matching the implicit trailing skip in
+/// `apply`.
+struct StepCursor<'a> {
+    steps: std::slice::Iter<'a, Step>,
+    head: Option<Step>,
+}
+
+impl<'a> StepCursor<'a> {
+    fn new(op: &'a [Step]) -> Self {
+        let mut cursor = StepCursor {
+            steps: op.iter(),
+            head: None,
+        };
+        cursor.advance();
+        cursor
+    }
+
+    fn advance(&mut self) {
+        self.head = self.steps.by_ref().find(|step| step_len(step) > 0).cloned();
+    }
+
+    fn is_done(&self) -> bool {
+        self.head.is_none()
+    }
+
+    fn peek(&self) -> &Step {
+        self.head.as_ref().unwrap_or(&INFINITE_SKIP)
+    }
+
+    /// Takes at most `n` units off the current step.
+    fn take(&mut self, n: usize) -> Step {
+        let step = match self.head.take() {
+            None => return Skip(n),
+            Some(step) => step,
+        };
+        if n >= step_len(&step) {
+            self.advance();
+            return step;
+        }
+        let (taken, rest) = match step {
+            Skip(m) => (Skip(n), Skip(m - n)),
+            Delete(m) => (Delete(n), Delete(m - n)),
+            Insert(s) => (Insert(s[..n].to_vec()), Insert(s[n..].to_vec())),
+        };
+        self.head = Some(rest);
+        taken
+    }
+}
+
+/// Appends `step` to `op`, merging it into the preceding steps where possible.
+fn push_step(op: &mut Vec<Step>, step: Step) {
+    if step_len(&step) == 0 {
+        return;
+    }
+    match (op.last_mut(), &step) {
+        (Some(Skip(n)), Skip(m)) => *n = n.saturating_add(*m),
+        (Some(Delete(n)), Delete(m)) => *n = n.saturating_add(*m),
+        (Some(Insert(s)), Insert(t)) => s.extend_from_slice(t),
+        (Some(Delete(n)), Insert(_)) => {
+            // Inserts go before deletes at the same position.
+            let delete = Delete(*n);
+            op.pop();
+            push_step(op, step);
+            op.push(delete);
+        }
+        _ => op.push(step),
+    }
+}
+
+/// Brings an operation into canonical form: drops empty steps, merges adjacent steps of the
+/// same kind, puts inserts before deletes at the same position and drops the trailing `Skip`.
+pub fn normalize(op: &[Step]) -> Op {
+    let mut result = vec![];
+    for step in op {
+        push_step(&mut result, step.clone());
+    }
+    if let Some(Skip(_)) = result.last() {
+        result.pop();
+    }
+    Op(result)
+}
+
+/// Returns true if the op doesn't change the document (it consists only of skips and empty
+/// steps).
+///
+/// Such text-neutral ops (e.g. selection updates, carrying cursor positions out of band) pass
+/// through `transform` unchanged, and applying them does nothing, even if they skip past the end
+/// of the document.
+pub fn is_identity(op: &[Step]) -> bool {
+    op.iter().all(|step| match step {
+        Skip(_) => true,
+        _ => step_len(step) == 0,
+    })
+}
+
+/// The kinds of changes an op makes, see `classify`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpKind {
+    /// Only skips (retains), the op doesn't change anything.
+    Identity,
+    /// Only inserts, nothing is deleted.
+    InsertOnly,
+    /// Only deletes, nothing is inserted.
+    DeleteOnly,
+    Mixed,
+}
+
+/// Tells which kinds of changes `op` makes, ignoring empty steps. `transform` uses it to take
+/// cheaper paths when `op2` only inserts or only deletes.
+pub fn classify(op: &[Step]) -> OpKind {
+    let inserts = op
+        .iter()
+        .any(|step| matches!(step, Insert(s) if !s.is_empty()));
+    let deletes = op.iter().any(|step| matches!(step, Delete(n) if *n > 0));
+    match (inserts, deletes) {
+        (false, false) => OpKind::Identity,
+        (true, false) => OpKind::InsertOnly,
+        (false, true) => OpKind::DeleteOnly,
+        (true, true) => OpKind::Mixed,
+    }
+}
+
+/// What an op is about at a high level, e.g. for routing ops to different handlers. See
+/// `categorize`, and `rich_text::categorize` for ops that can also change formatting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OpCategory {
+    /// Changes the text.
+    TextEdit,
+    /// Only changes formatting.
+    FormatOnly,
+    /// Changes nothing, only moves over the document (e.g. to carry a cursor position).
+    CursorOnly,
+    /// Changes both the text and formatting.
+    Mixed,
+}
+
+/// The category of `op`. Plain ops can't change formatting, so this is either `TextEdit` or
+/// `CursorOnly`.
+pub fn categorize(op: &[Step]) -> OpCategory {
+    match classify(op) {
+        OpKind::Identity => OpCategory::CursorOnly,
+        _ => OpCategory::TextEdit,
+    }
+}
+
+/// The part of the input document `op` changes, from its first to its last non-skip step. An
+/// insert touches just its position. Returns `None` for identity ops.
+///
+/// `transform` leaves `op1` unchanged (up to normalization) when `op2`'s range starts after
+/// `op1`'s range ends.
+pub fn affected_range(op: &[Step]) -> Option<Range<usize>> {
+    let mut range: Option<Range<usize>> = None;
+    let mut pos = 0;
+    for step in op {
+        let end = match step {
+            Skip(n) => {
+                pos += n;
+                continue;
+            }
+            Insert(s) if s.is_empty() => continue,
+            Insert(_) => pos,
+            Delete(0) => continue,
+            Delete(n) => pos + n,
+        };
+        range = Some(range.map_or(pos, |range| range.start)..end);
+        pos = end;
+    }
+    range
+}
+
+/// Which way a position moves when `op` inserts text exactly at it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Gravity {
+    /// Stay in front of the inserted text.
+    Before,
+    /// Move past the inserted text.
+    After,
+}
+
+/// Where the position `pos` of the input document ends up once `op` is applied (e.g. a pinned
+/// scroll offset during remote edits).
+///
+/// A position inside a deleted range, including its first byte, clamps to where the deletion
+/// starts, after any text `op` inserts right in front of it, whatever the `gravity`.
+/// `gravity` only decides between the two sides of text inserted exactly at `pos`.
+pub fn transform_position(pos: usize, op: &[Step], gravity: Gravity) -> usize {
+    transform_position_by_shape(pos, op.iter().map(TransformStep::shape), gravity)
+}
+
+/// `transform_position` for an op given by the shapes of its steps.
+fn transform_position_by_shape(
+    pos: usize,
+    op: impl IntoIterator<Item = Shape>,
+    gravity: Gravity,
+) -> usize {
+    let mut index = 0;
+    let mut new_index = 0;
+    for shape in op {
+        match shape {
+            Shape::Skip(n) => {
+                if pos < index + n {
+                    return new_index + (pos - index);
+                }
+                index += n;
+                new_index += n;
+            }
+            Shape::Insert(len) => {
+                if pos == index && gravity == Gravity::Before {
+                    return new_index;
+                }
+                new_index += len;
+            }
+            Shape::Delete(n) => {
+                if pos < index + n {
+                    return new_index;
+                }
+                index += n;
+            }
+        }
+    }
+    new_index + (pos - index)
+}
+
+/// Something pointing into a document that has to follow the document through ops (cursors,
+/// selections, anchors, ...).
+pub trait Transformable {
+    /// Where `self` ends up once `op` is applied. `side` decides, where the type doesn't, which
+    /// side of text inserted exactly at the position it ends up on, as in `transform`: `Left`
+    /// keeps it in front.
+    fn transform_through(self, op: &[Step], side: Side) -> Self;
+}
+
+fn side_gravity(side: Side) -> Gravity {
+    match side {
+        Left => Gravity::Before,
+        Right => Gravity::After,
+    }
+}
+
+/// A cursor.
+impl Transformable for usize {
+    fn transform_through(self, op: &[Step], side: Side) -> usize {
+        transform_position(self, op, side_gravity(side))
+    }
+}
+
+/// A selection. Text inserted at either end of a non-empty selection stays outside of it; an
+/// empty selection moves like a cursor.
+impl Transformable for Range<usize> {
+    fn transform_through(self, op: &[Step], side: Side) -> Range<usize> {
+        if self.start >= self.end {
+            let pos = self.start.transform_through(op, side);
+            return pos..pos;
+        }
+        let start = transform_position(self.start, op, Gravity::After);
+        let end = transform_position(self.end, op, Gravity::Before);
+        start..end.max(start)
+    }
+}
+
+/// A position with its own gravity (e.g. a marker attached to the text before or after it).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Anchor {
+    pub pos: usize,
+    pub gravity: Gravity,
+}
+
+/// Ignores `side`, the anchor's `gravity` decides.
+impl Transformable for Anchor {
+    fn transform_through(self, op: &[Step], _side: Side) -> Anchor {
+        Anchor {
+            pos: transform_position(self.pos, op, self.gravity),
+            gravity: self.gravity,
+        }
+    }
+}
+
+/// Whether `op` changes anything in `range` of the input document: deletes a byte in it or
+/// inserts at a position from `range.start` to `range.end` inclusive (right next to the range
+/// counts, as it changes the range's surroundings).
+pub fn touches(op: &[Step], range: Range<usize>) -> bool {
+    let mut pos = 0;
+    for step in op {
+        if pos > range.end {
+            return false;
+        }
+        match step {
+            Skip(n) => pos = pos.saturating_add(*n),
+            Insert(s) if !s.is_empty() && range.start <= pos => return true,
+            Insert(_) => {}
+            Delete(n) => {
+                let end = pos.saturating_add(*n);
+                if *n > 0 && pos < range.end && range.start < end {
+                    return true;
+                }
+                pos = end;
+            }
+        }
+    }
+    false
+}
+
 /// Takes two operations defined on the same initial document,
 /// and returns an operation equivalent to `op1` which can be applied after `op2`.
-pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
-    vec![] // TODO
+///
+/// When both ops insert at the same position, `op1`'s insert goes first if `side` is `Left`.
+///
+/// Transforming against a composition is the same as transforming against its parts in turn,
+/// `transform(x, compose(a, b), side) == transform(transform(x, a, side), b, side)` up to
+/// normalization, as long as `x` (normalized) only deletes, or `a` and `b` both only insert or
+/// both only delete. Otherwise the two can differ when `x` inserts next to text that `a` and `b`
+/// together replace, since the composition no longer tells where in the replaced range its
+/// insert came from: with `Left`, `x = [Skip(1), Insert("x")]`, `a = [Delete(1)]` and
+/// `b = [Insert("b")]` give `[Skip(1), Insert("x")]` against the composition but `[Insert("x")]`
+/// against `a` then `b`. `Client` transforms remote ops against its buffered edits composed
+/// into one op, so it only matches transforming against each edit in the restricted cases; that
+/// only changes the order of tied inserts, as the server transforms against the same composed op.
+///
+/// Never panics, even for ops that aren't valid on any document.
+pub fn transform(op1: &[Step], op2: &[Step], side: Side) -> Op {
+    if is_identity(op1) {
+        return op1.to_vec().into();
+    }
+    if is_identity(op2) {
+        return op1.to_vec().into();
+    }
+    let mut result = vec![];
+    transform_into(op1, op2, side, &mut result);
+    Op(result)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Like `transform`, but returns `None` instead of a copy of `op1` when `op1` can be applied
+/// after `op2` as it is (e.g. `op2` only changes the document after `op1`), so that callers can
+/// keep the op they have. Cheap to check when `op2` is an identity or starts after `op1`'s last
+/// change; otherwise the transformed op is compared with `op1`.
+pub fn transform_opt(op1: &[Step], op2: &[Step], side: Side) -> Option<Op> {
+    if is_identity(op1) || is_identity(op2) {
+        return None;
+    }
+    if let (Some(range1), Some(range2)) = (affected_range(op1), affected_range(op2)) {
+        if range2.start > range1.end {
+            return None;
+        }
+    }
+    let result = transform(op1, op2, side);
+    if result[..] == *op1 {
+        None
+    } else {
+        Some(result)
+    }
+}
 
-    #[test]
-    fn test_apply_insert() {
-        let mut doc = b"abc".to_vec();
-        apply(&mut doc, &[Skip(1), Insert(b"xyz".to_vec())]);
-        assert_eq!(doc, b"axyzbc");
+/// Like `op1 = transform(&op1, op2, side)`, but without allocating when either op is an
+/// identity.
+pub fn transform_in_place(op1: &mut Op, op2: &[Step], side: Side) {
+    transform_in_place_with_scratch(op1, op2, side, &mut vec![]);
+}
+
+/// Like `transform_in_place`, but builds the result in `scratch` and then swaps it into `op1`,
+/// leaving `scratch` with the old steps. Reusing the same scratch buffer (e.g. when transforming
+/// an op against a whole history) avoids allocating a new one for every transform.
+pub fn transform_in_place_with_scratch(
+    op1: &mut Op,
+    op2: &[Step],
+    side: Side,
+    scratch: &mut Vec<Step>,
+) {
+    if is_identity(op1) || is_identity(op2) {
+        return;
     }
+    scratch.clear();
+    transform_into(&op1.0, op2, side, scratch);
+    std::mem::swap(&mut op1.0, scratch);
+}
 
-    #[test]
-    fn test_apply_delete() {
-        let mut doc = b"abcd".to_vec();
-        apply(&mut doc, &[Skip(1), Delete(2)]);
+/// A step of an `OpRef`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum StepRef<'a> {
+    Skip(usize),
+    Insert(Cow<'a, [u8]>),
+    Delete(usize),
+}
+
+/// An op whose inserted chunks may be borrowed from another op, see `transform_borrowed`.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct OpRef<'a>(pub Vec<StepRef<'a>>);
+
+impl<'a> OpRef<'a> {
+    /// Copies the op out, cloning the borrowed chunks.
+    pub fn to_op(&self) -> Op {
+        self.0
+            .iter()
+            .map(|step| match step {
+                StepRef::Skip(n) => Skip(*n),
+                StepRef::Insert(s) => Insert(s.to_vec()),
+                StepRef::Delete(n) => Delete(*n),
+            })
+            .collect()
+    }
+}
+
+impl<'a> From<OpRef<'a>> for Op {
+    fn from(op: OpRef<'a>) -> Op {
+        op.0.into_iter()
+            .map(|step| match step {
+                StepRef::Skip(n) => Skip(n),
+                StepRef::Insert(s) => Insert(s.into_owned()),
+                StepRef::Delete(n) => Delete(n),
+            })
+            .collect()
+    }
+}
+
+/// `push_step` for `StepRef`s. Merging two inserts is the only case that has to copy a chunk.
+fn push_step_ref<'a>(op: &mut Vec<StepRef<'a>>, step: StepRef<'a>) {
+    match (op.last_mut(), &step) {
+        (Some(StepRef::Skip(n)), StepRef::Skip(m)) => *n = n.saturating_add(*m),
+        (Some(StepRef::Delete(n)), StepRef::Delete(m)) => *n = n.saturating_add(*m),
+        (Some(StepRef::Insert(s)), StepRef::Insert(t)) => s.to_mut().extend_from_slice(t),
+        (Some(StepRef::Delete(n)), StepRef::Insert(_)) => {
+            let delete = StepRef::Delete(*n);
+            op.pop();
+            push_step_ref(op, step);
+            op.push(delete);
+        }
+        _ => op.push(step),
+    }
+}
+
+impl<'a> TransformResult<'a, Step> for Vec<StepRef<'a>> {
+    fn push_skip(&mut self, n: usize) {
+        push_step_ref(self, StepRef::Skip(n));
+    }
+
+    fn push_delete(&mut self, n: usize) {
+        push_step_ref(self, StepRef::Delete(n));
+    }
+
+    fn push_insert(&mut self, insert: &'a Step) {
+        push_step_ref(self, StepRef::Insert(Cow::Borrowed(insert.chunk())));
+    }
+
+    fn pop_trailing_skip(&mut self) {
+        if let Some(StepRef::Skip(_)) = self.last() {
+            self.pop();
+        }
+    }
+}
+
+/// Like `transform`, but the inserted chunks of the result borrow from `op1` instead of being
+/// cloned. Inserts of `op1` are never split by a transform, so they are only copied when two of
+/// them end up next to each other and are merged.
+pub fn transform_borrowed<'a>(op1: &'a [Step], op2: &[Step], side: Side) -> OpRef<'a> {
+    if is_identity(op1) || is_identity(op2) {
+        return OpRef(
+            op1.iter()
+                .map(|step| match step {
+                    Skip(n) => StepRef::Skip(*n),
+                    Insert(s) => StepRef::Insert(Cow::Borrowed(s)),
+                    Delete(n) => StepRef::Delete(*n),
+                })
+                .collect(),
+        );
+    }
+    let mut result = vec![];
+    transform_into(op1, op2, side, &mut result);
+    OpRef(result)
+}
+
+impl TransformStep for TaggedStep {
+    fn shape(&self) -> Shape {
+        match self {
+            TaggedStep::Skip(n) => Shape::Skip(*n),
+            TaggedStep::Insert(s, _) => Shape::Insert(s.len()),
+            TaggedStep::Delete(n) => Shape::Delete(*n),
+        }
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self {
+            TaggedStep::Insert(s, _) => s,
+            _ => &[],
+        }
+    }
+}
+
+/// `push_step` for `TaggedStep`s. Only inserts with the same tag are merged.
+fn push_tagged_step(op: &mut Vec<TaggedStep>, step: TaggedStep) {
+    if step.shape().len() == 0 {
+        return;
+    }
+    match (op.last_mut(), &step) {
+        (Some(TaggedStep::Skip(n)), TaggedStep::Skip(m)) => *n = n.saturating_add(*m),
+        (Some(TaggedStep::Delete(n)), TaggedStep::Delete(m)) => *n = n.saturating_add(*m),
+        (Some(TaggedStep::Insert(s, tag1)), TaggedStep::Insert(t, tag2)) if tag1 == tag2 => {
+            s.extend_from_slice(t)
+        }
+        (Some(TaggedStep::Delete(n)), TaggedStep::Insert(..)) => {
+            // Inserts go before deletes at the same position.
+            let delete = TaggedStep::Delete(*n);
+            op.pop();
+            push_tagged_step(op, step);
+            op.push(delete);
+        }
+        _ => op.push(step),
+    }
+}
+
+impl<'a> TransformResult<'a, TaggedStep> for Vec<TaggedStep> {
+    fn push_skip(&mut self, n: usize) {
+        push_tagged_step(self, TaggedStep::Skip(n));
+    }
+
+    fn push_delete(&mut self, n: usize) {
+        push_tagged_step(self, TaggedStep::Delete(n));
+    }
+
+    fn push_insert(&mut self, insert: &'a TaggedStep) {
+        push_tagged_step(self, insert.clone());
+    }
+
+    fn pop_trailing_skip(&mut self) {
+        if let Some(TaggedStep::Skip(_)) = self.last() {
+            self.pop();
+        }
+    }
+}
+
+/// `transform` for a tagged op: each of `op1`'s inserts keeps its tag wherever `op2` moves it.
+pub fn transform_tagged(op1: &TaggedOp, op2: &[Step], side: Side) -> TaggedOp {
+    if is_identity(op2) {
+        return op1.clone();
+    }
+    let mut result = vec![];
+    transform_into(&op1.0, op2, side, &mut result);
+    TaggedOp(result)
+}
+
+/// If `op` appends to a document of length `len` (`[Skip(len), Insert(entry)]`, where the skip
+/// may be missing for `len == 0`), returns `(len, entry)`.
+fn as_append(op: &[Step]) -> Option<(usize, &[u8])> {
+    match op {
+        [Insert(entry)] => Some((0, entry)),
+        [Skip(len), Insert(entry)] => Some((*len, entry)),
+        _ => None,
+    }
+}
+
+/// Like `transform`, with a fast path for append-only documents (e.g. event logs): when both ops
+/// append to the end of the same document, `op1` goes right after it for `Left` and after
+/// `op2`'s entry for `Right`, without walking the steps. Falls back to `transform` for other
+/// ops.
+pub fn transform_append(op1: &[Step], op2: &[Step], side: Side) -> Op {
+    match (as_append(op1), as_append(op2)) {
+        (Some((len1, entry1)), Some((len2, entry2))) if len1 == len2 && !entry2.is_empty() => {
+            let len = match side {
+                Left => len1,
+                Right => len1.saturating_add(entry2.len()),
+            };
+            normalize(&[Skip(len), Insert(entry1.to_vec())])
+        }
+        _ => transform(op1, op2, side),
+    }
+}
+
+/// An op together with a hint of the part of the input document it changes.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct RangedOp {
+    pub op: Op,
+    /// Has to contain `affected_range(&op)` (any range will do for an identity op):
+    /// `transform_ranged` trusts it without looking at the steps.
+    pub affected: Range<usize>,
+}
+
+impl From<Op> for RangedOp {
+    fn from(op: Op) -> Self {
+        let affected = affected_range(&op).unwrap_or(0..0);
+        RangedOp { op, affected }
+    }
+}
+
+/// Like `transform`, with a fast path for ops whose `affected` ranges are disjoint: `op1` is
+/// then only shifted by the length change of an `op2` entirely before it, without walking
+/// `op2`'s steps. The result's range is shifted along.
+pub fn transform_ranged(op1: &RangedOp, op2: &RangedOp, side: Side) -> RangedOp {
+    transform_disjoint(op1, op2).unwrap_or_else(|| transform(&op1.op, &op2.op, side).into())
+}
+
+/// The fast path of `transform_ranged`, or `None` if the ranges overlap or touch.
+fn transform_disjoint(op1: &RangedOp, op2: &RangedOp) -> Option<RangedOp> {
+    let (range1, range2) = (&op1.affected, &op2.affected);
+    let delta = if range2.start > range1.end {
+        0
+    } else if range2.end < range1.start {
+        len_delta(&op2.op)
+    } else {
+        return None;
+    };
+    let shift = |pos: usize| (pos as isize + delta) as usize;
+    let mut op = normalize(&op1.op);
+    if let Some(Skip(n)) = op.0.first_mut() {
+        *n = shift(*n);
+    }
+    Some(RangedOp {
+        op,
+        affected: shift(range1.start)..shift(range1.end),
+    })
+}
+
+/// How much of an op's deletion a concurrent op had already deleted.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ConflictInfo {
+    /// None of it (or the op doesn't delete anything).
+    Untouched,
+    /// Some of it; the transformed op deletes the rest.
+    PartiallyCancelled,
+    /// All of it; the transformed op doesn't delete anything.
+    FullyCancelled,
+}
+
+/// Number of bytes deleted by both `op1` and `op2`, defined on the same document.
+fn deleted_by_both(op1: &[Step], op2: &[Step]) -> usize {
+    let mut a = StepCursor::new(op1);
+    let mut b = StepCursor::new(op2);
+    let mut count: usize = 0;
+    while !(a.is_done() || b.is_done()) {
+        if let Insert(_) = a.peek() {
+            a.take(usize::MAX);
+        } else if let Insert(_) = b.peek() {
+            b.take(usize::MAX);
+        } else {
+            let n = min(step_len(a.peek()), step_len(b.peek()));
+            if let (Delete(_), Delete(_)) = (a.take(n), b.take(n)) {
+                count = count.saturating_add(n);
+            }
+        }
+    }
+    count
+}
+
+/// Like `transform`, but also tells how much of `op1`'s deletion was cancelled by `op2`
+/// deleting the same text (e.g. for telling the user their deletion conflicted).
+pub fn transform_with_conflict_info(op1: &[Step], op2: &[Step], side: Side) -> (Op, ConflictInfo) {
+    let cancelled = deleted_by_both(op1, op2);
+    let info = if cancelled == 0 {
+        ConflictInfo::Untouched
+    } else if cancelled == deleted_len(op1) {
+        ConflictInfo::FullyCancelled
+    } else {
+        ConflictInfo::PartiallyCancelled
+    };
+    (transform(op1, op2, side), info)
+}
+
+fn deleted_len(op: &[Step]) -> usize {
+    op.iter()
+        .map(|step| match step {
+            Delete(n) => *n,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Like `transform`, but first checks that both ops fit the document of length `doc_len` they're
+/// supposed to be defined on. An op built on a longer document (e.g. by a peer that saw inserts
+/// we haven't) would otherwise be transformed into an op past the end of the document.
+///
+/// Because of the implicit trailing skip, an op only tells how long the document is at least,
+/// so the length has to be given.
+pub fn transform_checked(
+    op1: &[Step],
+    op2: &[Step],
+    side: Side,
+    doc_len: usize,
+) -> Result<Op, ApplyError> {
+    for op in &[op1, op2] {
+        let input_len = input_len(op);
+        if input_len > doc_len {
+            return Err(ApplyError::OutOfBounds { input_len, doc_len });
+        }
+    }
+    Ok(transform(op1, op2, side))
+}
+
+/// Like `transform_checked`, but instead of failing on ops that don't fit the document of length
+/// `doc_len`, drops the parts of them past its end and transforms the rest (both ops are padded
+/// with the implicit trailing skip as usual). Inserts right at the end are kept.
+///
+/// This is lossy: the dropped edits are gone, and the result is only a best guess at what the
+/// author of `op1` meant. It's meant for relays that have to forward ops they can't validate,
+/// where a usable op is better than none. The result fits the document after `op2` with the same
+/// parts dropped, see `clamp_to_len`.
+pub fn transform_lenient(op1: &[Step], op2: &[Step], side: Side, doc_len: usize) -> Op {
+    transform(
+        &clamp_to_len(op1, doc_len),
+        &clamp_to_len(op2, doc_len),
+        side,
+    )
+}
+
+/// `op` without the parts past the end of a document of length `doc_len`, so that it can be
+/// applied to it.
+pub fn clamp_to_len(op: &[Step], doc_len: usize) -> Op {
+    let mut result = vec![];
+    let mut pos = 0;
+    for step in op {
+        let left = doc_len - pos;
+        let step = match step {
+            Skip(n) => Skip(min(*n, left)),
+            Delete(n) => Delete(min(*n, left)),
+            Insert(s) => Insert(s.clone()),
+        };
+        match step {
+            Skip(n) | Delete(n) => pos += n,
+            Insert(_) => {}
+        }
+        push_step(&mut result, step);
+    }
+    if let Some(Skip(_)) = result.last() {
+        result.pop();
+    }
+    Op(result)
+}
+
+/// Like `transform`, but orders concurrent inserts at the same position by their contents
+/// instead of by `Side`: the lexicographically smaller insert (or the shorter one, if one is a
+/// prefix of the other) goes first. Both peers get the same order whichever op they consider
+/// first, so no leader is needed to assign sides.
+pub fn transform_content_ordered(op1: &[Step], op2: &[Step]) -> Op {
+    if is_identity(op1) || is_identity(op2) {
+        return op1.to_vec().into();
+    }
+    transform_steps(op1, op2, |_, s1, s2| s1 < s2)
+}
+
+/// Like `transform`, but picks the side from the ids of the sites (peers) that made the ops:
+/// the insert of the site with the smaller id goes first. Since the ids are totally ordered,
+/// any number of concurrent inserts at the same position end up sorted by site whatever order
+/// they are delivered in, without a leader assigning sides.
+///
+/// `site1` and `site2` must differ: ops from the same site are never concurrent.
+pub fn transform_by_site<S: Ord>(op1: &[Step], site1: S, op2: &[Step], site2: S) -> Op {
+    debug_assert!(site1 != site2, "concurrent ops from the same site");
+    let side = if site1 < site2 { Left } else { Right };
+    transform(op1, op2, side)
+}
+
+/// What the transform loops need to know about a step of an op, whatever the representation of
+/// its inserted chunk: its kind and length.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Shape {
+    Skip(usize),
+    Insert(usize),
+    Delete(usize),
+}
+
+impl Shape {
+    fn len(self) -> usize {
+        match self {
+            Shape::Skip(n) | Shape::Insert(n) | Shape::Delete(n) => n,
+        }
+    }
+}
+
+/// A step of an op in some representation (`Step`, `intern::InternedStep`, ...), as seen by the
+/// transform loops. They never split inserts, so they can move `op1`'s inserts to the result as
+/// they are.
+pub(crate) trait TransformStep {
+    fn shape(&self) -> Shape;
+    /// The inserted bytes, empty for skips and deletes.
+    fn chunk(&self) -> &[u8];
+}
+
+impl TransformStep for Step {
+    fn shape(&self) -> Shape {
+        match self {
+            Skip(n) => Shape::Skip(*n),
+            Insert(s) => Shape::Insert(s.len()),
+            Delete(n) => Shape::Delete(*n),
+        }
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self {
+            Insert(s) => s,
+            _ => &[],
+        }
+    }
+}
+
+/// Where the transform loops put the transformed op, in some representation of ops. `'a` is the
+/// lifetime of `op1`, whose steps are of type `S`.
+pub(crate) trait TransformResult<'a, S> {
+    fn push_skip(&mut self, n: usize);
+    fn push_delete(&mut self, n: usize);
+    /// Appends one of `op1`'s inserts, unchanged.
+    fn push_insert(&mut self, insert: &'a S);
+    /// Called once the whole result is pushed.
+    fn pop_trailing_skip(&mut self);
+}
+
+impl<'a> TransformResult<'a, Step> for Vec<Step> {
+    fn push_skip(&mut self, n: usize) {
+        push_step(self, Skip(n));
+    }
+
+    fn push_delete(&mut self, n: usize) {
+        push_step(self, Delete(n));
+    }
+
+    fn push_insert(&mut self, insert: &'a Step) {
+        push_step(self, insert.clone());
+    }
+
+    fn pop_trailing_skip(&mut self) {
+        // `push_step` already merged the steps, only the trailing skip is left to drop.
+        if let Some(Skip(_)) = self.last() {
+            self.pop();
+        }
+    }
+}
+
+/// Walks the steps of an op in any representation, like `StepCursor`, but only splits skips and
+/// deletes: inserts are taken whole, by reference.
+struct ShapeCursor<'a, S> {
+    steps: std::slice::Iter<'a, S>,
+    /// The current step and what's left of it.
+    head: Option<(&'a S, Shape)>,
+}
+
+impl<'a, S: TransformStep> ShapeCursor<'a, S> {
+    fn new(op: &'a [S]) -> Self {
+        let mut cursor = ShapeCursor {
+            steps: op.iter(),
+            head: None,
+        };
+        cursor.advance();
+        cursor
+    }
+
+    fn advance(&mut self) {
+        self.head = self
+            .steps
+            .by_ref()
+            .map(|step| (step, step.shape()))
+            .find(|(_, shape)| shape.len() > 0);
+    }
+
+    fn is_done(&self) -> bool {
+        self.head.is_none()
+    }
+
+    /// What's left of the current step. Past the end of the op it's an infinite `Skip`.
+    fn peek(&self) -> Shape {
+        self.head
+            .map_or(Shape::Skip(usize::MAX), |(_, shape)| shape)
+    }
+
+    /// The bytes inserted by the current step, if it's an insert.
+    fn chunk(&self) -> &'a [u8] {
+        self.head.map_or(&[], |(step, _)| step.chunk())
+    }
+
+    /// Takes at most `n` units off the current skip or delete, or the whole current insert.
+    fn take(&mut self, n: usize) -> Shape {
+        let (step, shape) = match self.head {
+            None => return Shape::Skip(n),
+            Some(head) => head,
+        };
+        match shape {
+            Shape::Skip(m) if n < m => {
+                self.head = Some((step, Shape::Skip(m - n)));
+                Shape::Skip(n)
+            }
+            Shape::Delete(m) if n < m => {
+                self.head = Some((step, Shape::Delete(m - n)));
+                Shape::Delete(n)
+            }
+            _ => {
+                self.advance();
+                shape
+            }
+        }
+    }
+
+    /// Takes the current step, which has to be an insert.
+    fn take_insert(&mut self) -> &'a S {
+        let (step, _) = self.head.expect("no insert to take");
+        self.advance();
+        step
+    }
+}
+
+/// The transform of `op1`, in any representation, against `op2`, pushed to `result`: `transform`
+/// without the shortcuts for identity ops. Takes the cheaper paths for an `op2` that only
+/// inserts or only deletes.
+pub(crate) fn transform_into<'a, S: TransformStep, R: TransformResult<'a, S>>(
+    op1: &'a [S],
+    op2: &[Step],
+    side: Side,
+    result: &mut R,
+) {
+    match classify(op2) {
+        OpKind::InsertOnly => transform_against_inserts(op1, op2, side, result),
+        OpKind::DeleteOnly => transform_against_deletes(op1, op2, result),
+        OpKind::Identity | OpKind::Mixed => transform_steps_into(
+            op1,
+            op2,
+            |pos, _, _| insert_tiebreak(pos, pos, side) == Less,
+            result,
+        ),
+    }
+}
+
+/// `op1_first` decides whether `op1`'s insert goes before `op2`'s, given the position in the
+/// original document and the inserted chunks, when both insert at the same position.
+fn transform_steps(
+    op1: &[Step],
+    op2: &[Step],
+    op1_first: impl Fn(usize, &[u8], &[u8]) -> bool,
+) -> Op {
+    let mut result = vec![];
+    transform_steps_into(op1, op2, op1_first, &mut result);
+    Op(result)
+}
+
+/// Like `transform_steps`, but for `op1` in any representation, pushing the result to `result`.
+fn transform_steps_into<'a, S: TransformStep, R: TransformResult<'a, S>>(
+    op1: &'a [S],
+    op2: &[Step],
+    op1_first: impl Fn(usize, &[u8], &[u8]) -> bool,
+    result: &mut R,
+) {
+    let mut a = ShapeCursor::new(op1);
+    let mut b = ShapeCursor::new(op2);
+    // Position in the original document.
+    let mut pos = 0;
+    while !(a.is_done() && b.is_done()) {
+        match (a.peek(), b.peek()) {
+            (Shape::Insert(_), Shape::Insert(n)) if !op1_first(pos, a.chunk(), b.chunk()) => {
+                b.take(n);
+                result.push_skip(n);
+            }
+            (Shape::Insert(_), _) => result.push_insert(a.take_insert()),
+            (_, Shape::Insert(n)) => {
+                b.take(n);
+                result.push_skip(n);
+            }
+            (shape1, shape2) => {
+                let n = min(shape1.len(), shape2.len());
+                pos = pos.saturating_add(n);
+                match (a.take(n), b.take(n)) {
+                    // The text was already deleted by `op2`.
+                    (_, Shape::Delete(_)) => {}
+                    (Shape::Delete(_), _) => result.push_delete(n),
+                    _ => result.push_skip(n),
+                }
+            }
+        }
+    }
+    result.pop_trailing_skip();
+}
+
+/// `transform_steps_into` for an `op2` that doesn't delete anything: `op1` only has to make
+/// room for `op2`'s inserts.
+fn transform_against_inserts<'a, S: TransformStep, R: TransformResult<'a, S>>(
+    op1: &'a [S],
+    op2: &[Step],
+    side: Side,
+    result: &mut R,
+) {
+    // `op2`'s inserts, as (position in the input document, length).
+    let mut inserts = op2
+        .iter()
+        .scan(0, |pos, step| {
+            Some(match step {
+                Skip(n) => {
+                    *pos = n.saturating_add(*pos);
+                    None
+                }
+                Insert(s) if !s.is_empty() => Some((*pos, s.len())),
+                _ => None,
+            })
+        })
+        .flatten()
+        .peekable();
+    let mut a = ShapeCursor::new(op1);
+    let mut pos = 0;
+    while !a.is_done() {
+        match (a.peek(), inserts.peek()) {
+            (Shape::Insert(_), Some(&(at, _))) if insert_tiebreak(pos, at, side) == Less => {
+                result.push_insert(a.take_insert())
+            }
+            // Also reached in the middle of a delete, which then gets split around the insert:
+            // `op1` can't delete text it has never seen.
+            (_, Some(&(at, len))) if pos == at => {
+                inserts.next();
+                result.push_skip(len);
+            }
+            (shape, Some(&(at, _))) => {
+                let n = min(shape.len(), at - pos);
+                push_len(result, a.take(n));
+                pos += n;
+            }
+            (Shape::Insert(_), None) => result.push_insert(a.take_insert()),
+            (shape, None) => push_len(result, a.take(shape.len())),
+        }
+    }
+    result.pop_trailing_skip();
+}
+
+/// `transform_steps_into` for an `op2` that doesn't insert anything: `op1`'s steps over text
+/// `op2` deleted are dropped.
+fn transform_against_deletes<'a, S: TransformStep, R: TransformResult<'a, S>>(
+    op1: &'a [S],
+    op2: &[Step],
+    result: &mut R,
+) {
+    let mut a = ShapeCursor::new(op1);
+    let mut b = ShapeCursor::new(op2);
+    while !a.is_done() {
+        if let Shape::Insert(_) = a.peek() {
+            result.push_insert(a.take_insert());
+            continue;
+        }
+        let n = min(a.peek().len(), b.peek().len());
+        match (a.take(n), b.take(n)) {
+            (_, Shape::Delete(_)) => {}
+            (shape, _) => push_len(result, shape),
+        }
+    }
+    result.pop_trailing_skip();
+}
+
+/// Pushes a skip or a delete taken off `op1` as it is.
+fn push_len<'a, S, R: TransformResult<'a, S>>(result: &mut R, shape: Shape) {
+    match shape {
+        Shape::Delete(n) => result.push_delete(n),
+        _ => result.push_skip(shape.len()),
+    }
+}
+
+/// Composes two consecutive operations: the result is equivalent to applying `op1` and then
+/// `op2`.
+///
+/// Transforming against the result is only the same as transforming against `op1` and then
+/// `op2` in restricted cases, see `transform`.
+pub fn compose(op1: &[Step], op2: &[Step]) -> Op {
+    let mut a = StepCursor::new(op1);
+    let mut b = StepCursor::new(op2);
+    let mut result = vec![];
+    while !(a.is_done() && b.is_done()) {
+        if let Insert(_) = b.peek() {
+            push_step(&mut result, b.take(usize::MAX));
+            continue;
+        }
+        if let Delete(_) = a.peek() {
+            push_step(&mut result, a.take(usize::MAX));
+            continue;
+        }
+        let n = min(step_len(a.peek()), step_len(b.peek()));
+        match (a.take(n), b.take(n)) {
+            (Skip(_), Skip(_)) => push_step(&mut result, Skip(n)),
+            (Skip(_), Delete(_)) => push_step(&mut result, Delete(n)),
+            (Insert(s), Skip(_)) => push_step(&mut result, Insert(s)),
+            (Insert(_), Delete(_)) => {}
+            _ => unreachable!(),
+        }
+    }
+    Op(result)
+}
+
+/// The op undoing `op`, given the document `op` was applied to: it deletes what `op` inserted
+/// and inserts back what `op` deleted.
+///
+/// Panics if `op` goes past the end of `doc_before`.
+pub fn invert(op: &[Step], doc_before: &[u8]) -> Op {
+    let mut pos = 0;
+    let mut result = vec![];
+    for step in op {
+        match step {
+            Skip(n) => {
+                push_step(&mut result, Skip(*n));
+                pos += n;
+            }
+            Insert(s) => push_step(&mut result, Delete(s.len())),
+            Delete(n) => {
+                push_step(&mut result, Insert(doc_before[pos..pos + n].to_vec()));
+                pos += n;
+            }
+        }
+    }
+    normalize(&result)
+}
+
+/// Debug check that `compose` and `invert` agree: composing `op` with its inverse must give an
+/// op that leaves `doc_before` as it is.
+///
+/// The composed op is an identity up to re-inserting deleted bytes, since `compose` doesn't
+/// cancel a delete against an insert of the same text: so besides skips, only inserts that are
+/// immediately followed by the delete of the same bytes of `doc_before` are allowed.
+pub fn is_identity_after_compose(op: &[Step], doc_before: &[u8]) -> bool {
+    let composed = normalize(&compose(op, &invert(op, doc_before)));
+    let mut pos = 0;
+    let mut steps = composed.iter();
+    while let Some(step) = steps.next() {
+        match (step, steps.clone().next()) {
+            (Skip(n), _) => pos += n,
+            (Insert(s), Some(Delete(n)))
+                if s.len() == *n && doc_before.get(pos..pos + n) == Some(&s[..]) =>
+            {
+                steps.next();
+                pos += n;
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Composes a sequence of consecutive operations into one.
+pub fn compose_all(ops: &[Op]) -> Op {
+    ops.iter().fold(Op::default(), |acc, op| compose(&acc, op))
+}
+
+/// An op in the sequence given to `compose_iter` doesn't fit the document left by the previous
+/// ones.
+#[derive(Eq, PartialEq, Debug)]
+pub struct ComposeError {
+    /// Position of the op in the sequence.
+    pub index: usize,
+    pub error: ApplyError,
+}
+
+/// Like `compose_all`, but takes the ops one at a time, so that they don't all have to be in
+/// memory (e.g. when compacting a log read from disk).
+///
+/// Starting from a document of length `doc_len`, checks that each op fits the document the
+/// previous ones leave behind.
+pub fn compose_iter(doc_len: usize, ops: impl IntoIterator<Item = Op>) -> Result<Op, ComposeError> {
+    let mut state = DocState::new(doc_len, 0);
+    let mut result = Op::default();
+    for (index, op) in ops.into_iter().enumerate() {
+        state
+            .apply(&op)
+            .map_err(|error| ComposeError { index, error })?;
+        result = compose(&result, &op);
+    }
+    Ok(result)
+}
+
+/// Compacts a burst of consecutive edits (e.g. everything typed before a pause) into a single
+/// normalized op describing the net change.
+pub fn compact_burst(ops: &[Op]) -> Op {
+    normalize(&compose_all(ops))
+}
+
+/// Maps positions in the intermediate versions of a compacted sequence of ops to positions in
+/// the document after the whole sequence, so that anchors taken against versions that no longer
+/// exist can be remapped. Version `k` is the document after the first `k` ops.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PositionMap {
+    /// The shape of each op: how much it skips, inserts and deletes where, but not what it
+    /// inserts.
+    shapes: Vec<Vec<Shape>>,
+}
+
+impl PositionMap {
+    /// Where `pos` in `version` ends up after the last op. See `transform_position`.
+    ///
+    /// `None` if there's no such version, i.e. `version` is more than the number of ops.
+    pub fn map(&self, version: usize, pos: usize, gravity: Gravity) -> Option<usize> {
+        let shapes = self.shapes.get(version..)?;
+        Some(shapes.iter().fold(pos, |pos, shapes| {
+            transform_position_by_shape(pos, shapes.iter().copied(), gravity)
+        }))
+    }
+}
+
+/// Like `compact_burst`, also returning the `PositionMap` of the compacted ops.
+pub fn compact_burst_with_positions(ops: &[Op]) -> (Op, PositionMap) {
+    let shapes = ops
+        .iter()
+        .map(|op| op.iter().map(TransformStep::shape).collect())
+        .collect();
+    (compact_burst(ops), PositionMap { shapes })
+}
+
+/// An op replacing `delete_count` bytes at `index` with `insert` (e.g. typing over a
+/// selection), in normalized form.
+pub fn splice(index: usize, delete_count: usize, insert: impl AsRef<[u8]>) -> Op {
+    normalize(&[
+        Skip(index),
+        Delete(delete_count),
+        Step::insert(insert.as_ref()),
+    ])
+}
+
+/// An op wrapping the bytes `start..end` in `prefix` and `suffix` (e.g. putting a selection in
+/// quotes or brackets). The two inserts transform independently: text inserted concurrently
+/// inside the range stays inside, and concurrent edits elsewhere only move the inserts.
+///
+/// Panics if `start > end`.
+pub fn wrap_range(start: usize, end: usize, prefix: &[u8], suffix: &[u8]) -> Op {
+    assert!(start <= end, "wrap_range: start {} > end {}", start, end);
+    normalize(&[
+        Skip(start),
+        Step::insert(prefix),
+        Skip(end - start),
+        Step::insert(suffix),
+    ])
+}
+
+/// An op reversing the bytes `start..end` of `doc`, by deleting them and inserting them back in
+/// reverse order.
+///
+/// Since `transform` only sees a delete and an insert, a concurrent edit inside the range isn't
+/// carried into the reversed text: a concurrent insert survives next to it, and a concurrent
+/// delete of reversed bytes is lost.
+pub fn reverse_range(doc: &[u8], start: usize, end: usize) -> Op {
+    let reversed: Vec<u8> = doc[start..end].iter().rev().copied().collect();
+    splice(start, end - start, reversed)
+}
+
+/// An op deleting the whole document of length `doc_len` ("select all + delete").
+///
+/// A clear is an ordinary delete as far as `transform` is concerned, so text inserted
+/// concurrently with a clear survives it. That's deliberate: the author of the insert never saw
+/// the text the clear removed, and silently dropping their edit would lose work. Concurrent
+/// deletes are simply absorbed by the clear.
+pub fn clear_op(doc_len: usize) -> Op {
+    normalize(&[Delete(doc_len)])
+}
+
+/// An op bootstrapping a brand-new (empty) document with `content`.
+///
+/// It's a plain insert at position 0, so it transforms like one: any op on the empty document
+/// is itself an insert at 0, and the two end up side by side, ordered by `Side`. Ops made after
+/// the initial op has been applied transform against later history as usual.
+pub fn initial_op(content: &[u8]) -> Op {
+    normalize(&[Step::insert(content)])
+}
+
+/// Splits `op` into a sequence of ops, each inserting at most `max_bytes` bytes, such that
+/// applying them in order is equivalent to applying `op`. Later ops skip over the text inserted
+/// by earlier ones.
+pub fn split_by_size(op: &[Step], max_bytes: usize) -> Vec<Op> {
+    assert!(max_bytes > 0, "max_bytes must be positive");
+    let mut ops = vec![];
+    let mut current = vec![];
+    // Position in the document after applying `ops` and `current`.
+    let mut index = 0;
+    let mut budget = max_bytes;
+    for step in op {
+        match step {
+            Skip(n) => {
+                push_step(&mut current, Skip(*n));
+                index += n;
+            }
+            Delete(n) => {
+                push_step(&mut current, Delete(*n));
+            }
+            Insert(s) => {
+                let mut s = &s[..];
+                while !s.is_empty() {
+                    if budget == 0 {
+                        ops.push(normalize(&current));
+                        current = vec![Skip(index)];
+                        budget = max_bytes;
+                    }
+                    let n = min(budget, s.len());
+                    push_step(&mut current, Insert(s[..n].to_vec()));
+                    index += n;
+                    budget -= n;
+                    s = &s[n..];
+                }
+            }
+        }
+    }
+    if ops.is_empty() || !is_identity(&current) {
+        ops.push(normalize(&current));
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_insert() {
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &[Skip(1), Insert(b"xyz".to_vec())]);
+        assert_eq!(doc, b"axyzbc");
+    }
+
+    #[test]
+    fn test_apply_delete() {
+        let mut doc = b"abcd".to_vec();
+        apply(&mut doc, &[Skip(1), Delete(2)]);
+        assert_eq!(doc, b"ad");
+    }
+
+    #[test]
+    fn test_apply_steps_after_delete() {
+        let mut doc = b"abcdef".to_vec();
+        apply(
+            &mut doc,
+            &[
+                Delete(2),
+                Insert(b"x".to_vec()),
+                Skip(1),
+                Delete(1),
+                Skip(1),
+            ],
+        );
+        assert_eq!(doc, b"xcef");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_apply_delete_past_end() {
+        apply(&mut b"ab".to_vec(), &[Skip(1), Delete(2)]);
+    }
+
+    /// Stores the text in fixed-size lines, like an editor might.
+    struct Lines(Vec<Vec<u8>>);
+
+    impl Lines {
+        fn joined(&self) -> Vec<u8> {
+            self.0.concat()
+        }
+
+        fn set(&mut self, text: Vec<u8>) {
+            self.0 = text.chunks(2).map(|line| line.to_vec()).collect();
+        }
+    }
+
+    impl TextBuffer for Lines {
+        fn insert_at(&mut self, index: usize, bytes: &[u8]) {
+            let mut text = self.joined();
+            text.splice(index..index, bytes.iter().cloned());
+            self.set(text);
+        }
+
+        fn delete_range(&mut self, range: Range<usize>) {
+            let mut text = self.joined();
+            text.drain(range);
+            self.set(text);
+        }
+
+        fn len(&self) -> usize {
+            self.0.iter().map(|line| line.len()).sum()
+        }
+    }
+
+    #[test]
+    fn test_apply_custom_buffer() {
+        let mut doc = Lines(vec![]);
+        doc.set(b"abcde".to_vec());
+        apply(
+            &mut doc,
+            &[Skip(1), Insert(b"xyz".to_vec()), Skip(3), Delete(1)],
+        );
+        assert_eq!(doc.joined(), b"axyzbcd");
+        assert_eq!(doc.len(), 7);
+        assert_eq!(doc.0[0], b"ax");
+    }
+
+    #[test]
+    fn test_apply_with_scratch() {
+        let mut doc = b"abcdef".to_vec();
+        let mut scratch = Vec::new();
+        apply_with_scratch(
+            &mut doc,
+            &[Skip(1), Delete(2), Insert(b"xyz".to_vec()), Skip(1)],
+            &mut scratch,
+        );
+        assert_eq!(doc, b"axyzdef");
+        assert_eq!(scratch, b"abcdef");
+    }
+
+    #[test]
+    fn test_apply_with_scratch_large_op() {
+        let initial: Doc = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut op = vec![];
+        for i in 0..10_000 {
+            op.push(Skip(9));
+            op.push(Insert(vec![i as u8; 3]));
+        }
+        op.push(Delete(1000));
+
+        let mut expected = initial.clone();
+        apply(&mut expected, &op);
+
+        let mut doc = initial.clone();
+        let mut scratch = Vec::new();
+        apply_with_scratch(&mut doc, &op, &mut scratch);
+        assert_eq!(doc, expected);
+        assert_eq!(doc.len(), initial.len() + 30_000 - 1000);
+    }
+
+    #[test]
+    fn test_apply_at() {
+        let mut buffer = b"line 1\nline 2\nline 3\n".to_vec();
+        let op = vec![
+            Delete(4),
+            Insert(b"LINE".to_vec()),
+            Skip(1),
+            Delete(1),
+            Insert(b"two".to_vec()),
+        ];
+        assert_eq!(apply_at(&mut buffer, 7, &op), Ok(()));
+        assert_eq!(buffer, b"line 1\nLINE two\nline 3\n");
+
+        assert_eq!(
+            apply_at(&mut buffer, 18, &[Skip(2), Delete(4)]),
+            Err(ApplyError::OutOfBounds {
+                input_len: 6,
+                doc_len: 5
+            })
+        );
+        assert_eq!(
+            apply_at(&mut buffer, 30, &[]),
+            Err(ApplyError::OutOfBounds {
+                input_len: 0,
+                doc_len: 0
+            })
+        );
+        assert_eq!(buffer, b"line 1\nLINE two\nline 3\n");
+    }
+
+    #[test]
+    fn test_try_apply_with_readonly() {
+        let readonly = [2..4, 6..7];
+        let mut doc = b"ab[]cd!ef".to_vec();
+        for op in &[
+            vec![Skip(3), Insert(b"x".to_vec())],
+            vec![Skip(1), Delete(2)],
+            vec![Skip(6), Delete(1)],
+        ] {
+            let error = try_apply_with_readonly(&mut doc, op, &readonly).unwrap_err();
+            assert!(matches!(error, ApplyError::ReadOnly(_)));
+        }
+        assert_eq!(
+            try_apply_with_readonly(&mut doc, &[Skip(1), Delete(2)], &readonly),
+            Err(ApplyError::ReadOnly(2))
+        );
+        assert_eq!(doc, b"ab[]cd!ef");
+
+        // Edits outside, and inserts at the edges of read-only ranges, are allowed.
+        let op = vec![
+            Skip(1),
+            Delete(1),
+            Insert(b"<".to_vec()),
+            Skip(2),
+            Insert(b">".to_vec()),
+            Delete(1),
+            Skip(2),
+            Insert(b"?".to_vec()),
+        ];
+        assert_eq!(try_apply_with_readonly(&mut doc, &op, &readonly), Ok(()));
+        assert_eq!(doc, b"a<[]>d!?ef");
+    }
+
+    /// The counterexample from `transform`'s doc: transforming against a replacement composed
+    /// into one op breaks the tie differently from transforming against its parts.
+    #[test]
+    fn test_transform_against_compose_with_replacement() {
+        let x = vec![Skip(1), Insert(b"x".to_vec())];
+        let a = vec![Delete(1)];
+        let b = vec![Insert(b"b".to_vec())];
+        assert_eq!(compose(&a, &b), vec![Insert(b"b".to_vec()), Delete(1)]);
+        // Against each: `a` moves the insert to 0, where it wins the tie with `b`'s.
+        assert_eq!(
+            transform(&transform(&x, &a, Left), &b, Left),
+            vec![Insert(b"x".to_vec())]
+        );
+        // Against the composition: the insert was after the replaced byte.
+        assert_eq!(
+            transform(&x, &compose(&a, &b), Left),
+            vec![Skip(1), Insert(b"x".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_invert() {
+        let doc = b"abcdef".to_vec();
+        let op = vec![
+            Skip(1),
+            Delete(2),
+            Insert(b"xyz".to_vec()),
+            Skip(1),
+            Delete(1),
+        ];
+        let inverse = invert(&op, &doc);
+        assert_eq!(
+            inverse,
+            vec![
+                Skip(1),
+                Insert(b"bc".to_vec()),
+                Delete(3),
+                Skip(1),
+                Insert(b"e".to_vec())
+            ]
+        );
+        assert_eq!(applied(&applied(&doc, &op), &inverse), doc);
+        assert!(is_identity_after_compose(&op, &doc));
+    }
+
+    #[test]
+    fn test_touches() {
+        let op = vec![Skip(2), Delete(2), Skip(3), Insert(b"x".to_vec())];
+        // Entirely before.
+        assert!(!touches(&op, 0..2));
+        // Entirely after.
+        assert!(!touches(&op, 8..10));
+        assert!(!touches(&op, 4..6));
+        // Overlapping.
+        assert!(touches(&op, 1..3));
+        assert!(touches(&op, 3..9));
+        assert!(touches(&op, 2..3));
+        // Inserts right next to the range.
+        assert!(touches(&op, 5..7));
+        assert!(touches(&op, 7..9));
+        assert!(touches(&op, 7..7));
+
+        assert!(!touches(&[Skip(2), Delete(0), Insert(vec![])], 0..5));
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&[]), OpKind::Identity);
+        assert_eq!(
+            classify(&[Skip(2), Insert(vec![]), Delete(0)]),
+            OpKind::Identity
+        );
+        assert_eq!(
+            Op(vec![Skip(2), Insert(b"x".to_vec())]).classify(),
+            OpKind::InsertOnly
+        );
+        assert_eq!(
+            classify(&[Delete(1), Skip(2), Delete(1)]),
+            OpKind::DeleteOnly
+        );
+        assert_eq!(classify(&[Insert(b"x".to_vec()), Delete(1)]), OpKind::Mixed);
+    }
+
+    #[test]
+    fn test_categorize() {
+        assert_eq!(
+            categorize(&[Skip(2), Insert(b"x".to_vec())]),
+            OpCategory::TextEdit
+        );
+        assert_eq!(categorize(&[Skip(2), Delete(1)]), OpCategory::TextEdit);
+        assert_eq!(categorize(&[Skip(5)]), OpCategory::CursorOnly);
+        assert_eq!(categorize(&[]), OpCategory::CursorOnly);
+    }
+
+    #[test]
+    fn test_transform_opt() {
+        let op1 = vec![Skip(2), Insert(b"x".to_vec()), Skip(1), Delete(1)];
+        assert_eq!(transform_opt(&op1, &[Skip(5), Delete(2)], Left), None);
+        assert_eq!(transform_opt(&op1, &[], Left), None);
+        // Overlapping ranges, but nothing moves.
+        assert_eq!(
+            transform_opt(&op1, &[Skip(4), Insert(b"y".to_vec())], Left),
+            None
+        );
+
+        let op2 = vec![Insert(b"ab".to_vec())];
+        assert_eq!(
+            transform_opt(&op1, &op2, Left),
+            Some(transform(&op1, &op2, Left))
+        );
+        assert_eq!(
+            transform_opt(&op1, &[Skip(3), Delete(1)], Left),
+            Some(Op(vec![Skip(2), Insert(b"x".to_vec())]))
+        );
+    }
+
+    #[test]
+    fn test_transform_borrowed() {
+        let op1 = Op(vec![Skip(1), Insert(b"ab".to_vec()), Skip(2), Delete(1)]);
+        let op2 = Op(vec![Insert(b"xyz".to_vec()), Skip(2), Delete(1)]);
+        let transformed = transform_borrowed(&op1, &op2, Left);
+        assert_eq!(
+            transformed,
+            OpRef(vec![
+                StepRef::Skip(4),
+                StepRef::Insert(Cow::Borrowed(b"ab")),
+                StepRef::Skip(1),
+                StepRef::Delete(1),
+            ])
+        );
+        match (&transformed.0[1], &op1[1]) {
+            (StepRef::Insert(Cow::Borrowed(chunk)), Insert(s)) => {
+                assert_eq!(chunk.as_ptr(), s.as_ptr())
+            }
+            step => panic!("not borrowed: {:?}", step),
+        }
+        assert_eq!(transformed.to_op(), transform(&op1, &op2, Left));
+        assert_eq!(Op::from(transformed), transform(&op1, &op2, Left));
+    }
+
+    #[test]
+    fn test_op_as_collection() {
+        let op = Op(vec![Skip(2), Insert(b"xy".to_vec()), Delete(1)]);
+        assert_eq!(op.len(), 3);
+        assert!(!op.is_empty());
+        assert!(Op::default().is_empty());
+        assert_eq!(op[1], Insert(b"xy".to_vec()));
+        assert_eq!(op.last(), Some(&Delete(1)));
+
+        let deletes: usize = (&op)
+            .into_iter()
+            .map(|step| match step {
+                Delete(n) => *n,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(deletes, 1);
+        let steps: Vec<Step> = op.clone().into_iter().collect();
+        assert_eq!(steps, op.0);
+        assert_eq!(steps.into_iter().collect::<Op>(), op);
+    }
+
+    #[test]
+    fn test_input_len() {
+        assert_eq!(input_len(&[]), 0);
+        assert_eq!(
+            input_len(&[Skip(2), Insert(b"abc".to_vec()), Delete(3), Skip(1)]),
+            6
+        );
+        assert_eq!(input_len(&[Skip(usize::MAX), Skip(2)]), usize::MAX);
+    }
+
+    #[test]
+    fn test_len_delta() {
+        assert_eq!(len_delta(&[]), 0);
+        // Insert-heavy.
+        assert_eq!(
+            len_delta(&[Skip(2), Insert(b"abcd".to_vec()), Delete(1)]),
+            3
+        );
+        // Delete-heavy.
+        assert_eq!(len_delta(&[Delete(5), Skip(1), Insert(b"x".to_vec())]), -4);
+        // Balanced.
+        assert_eq!(len_delta(&[Insert(b"xy".to_vec()), Skip(3), Delete(2)]), 0);
+
+        for op in &[
+            vec![Skip(1), Delete(2), Insert(b"xyz".to_vec())],
+            vec![Delete(6)],
+            vec![Insert(b"xy".to_vec()), Delete(2)],
+        ] {
+            let mut doc = b"abcdef".to_vec();
+            apply_with_scratch(&mut doc, op, &mut vec![]);
+            assert_eq!(doc.len() as isize, 6 + len_delta(op));
+        }
+    }
+
+    #[test]
+    fn test_try_apply() {
+        let mut doc = b"abcdef".to_vec();
+        assert_eq!(
+            try_apply(&mut doc, &[Skip(1), Delete(2), Insert(b"x".to_vec())]),
+            Ok(())
+        );
+        assert_eq!(doc, b"axdef");
+
+        // Fine up to the last step.
+        let op = vec![
+            Delete(1),
+            Insert(b"y".to_vec()),
+            Skip(2),
+            Insert(b"z".to_vec()),
+            Delete(3),
+        ];
+        assert_eq!(
+            try_apply(&mut doc, &op),
+            Err(ApplyError::OutOfBounds {
+                input_len: 6,
+                doc_len: 5
+            })
+        );
+        assert_eq!(doc, b"axdef");
+
+        // Lengths adding up past `usize::MAX`.
+        assert_eq!(
+            try_apply(&mut doc, &[Skip(usize::MAX), Delete(2)]),
+            Err(ApplyError::OutOfBounds {
+                input_len: usize::MAX,
+                doc_len: 5
+            })
+        );
+        assert_eq!(doc, b"axdef");
+    }
+
+    #[test]
+    fn test_apply_versioned() {
+        let mut doc = b"abc".to_vec();
+        let vop = VersionedOp {
+            base: 3,
+            op: Op(vec![Skip(1), Delete(1)]),
+        };
+        assert_eq!(apply_versioned(&mut doc, 3, &vop), Ok(4));
+        assert_eq!(doc, b"ac");
+
+        assert_eq!(
+            apply_versioned(&mut doc, 4, &vop),
+            Err(ApplyError::VersionMismatch {
+                base: 3,
+                current: 4
+            })
+        );
+        assert_eq!(doc, b"ac");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(&[
+                Skip(1),
+                Skip(0),
+                Skip(2),
+                Delete(1),
+                Insert(b"a".to_vec()),
+                Delete(1),
+                Insert(b"b".to_vec()),
+                Insert(vec![]),
+                Skip(3),
+            ]),
+            vec![Skip(3), Insert(b"ab".to_vec()), Delete(2)]
+        );
+        assert_eq!(normalize(&[Skip(5)]), vec![]);
+    }
+
+    #[test]
+    fn test_compose() {
+        let op1 = vec![Skip(1), Insert(b"xyz".to_vec())];
+        let op2 = vec![Skip(2), Delete(2), Skip(1), Insert(b"!".to_vec())];
+        assert_eq!(
+            compose(&op1, &op2),
+            vec![
+                Skip(1),
+                Insert(b"x".to_vec()),
+                Skip(1),
+                Insert(b"!".to_vec())
+            ]
+        );
+
+        let mut doc = b"abc".to_vec();
+        let mut scratch = vec![];
+        apply_with_scratch(&mut doc, &compose(&op1, &op2), &mut scratch);
+        assert_eq!(doc, b"axb!c");
+    }
+
+    #[test]
+    fn test_compose_iter() {
+        let ops = vec![
+            Op(vec![Insert(b"abc".to_vec())]),
+            Op(vec![Skip(1), Delete(1)]),
+            Op(vec![Skip(2), Insert(b"d".to_vec())]),
+        ];
+        assert_eq!(compose_iter(0, ops.clone()), Ok(compose_all(&ops)));
+
+        let ops = vec![
+            Op(vec![Insert(b"ab".to_vec())]),
+            Op(vec![Skip(1), Delete(2)]),
+        ];
+        assert_eq!(
+            compose_iter(0, ops),
+            Err(ComposeError {
+                index: 1,
+                error: ApplyError::OutOfBounds {
+                    input_len: 3,
+                    doc_len: 2
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_compact_burst_type_then_partial_delete() {
+        let mut burst = vec![];
+        for (i, &c) in b"hello".iter().enumerate() {
+            burst.push(Op(vec![Skip(3 + i), Insert(vec![c])]));
+        }
+        // Backspace twice.
+        burst.push(Op(vec![Skip(7), Delete(1)]));
+        burst.push(Op(vec![Skip(6), Delete(1)]));
+        assert_eq!(
+            compact_burst(&burst),
+            vec![Skip(3), Insert(b"hel".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_compact_burst_with_positions() {
+        let ops = vec![
+            Op(vec![Insert(b"xy".to_vec())]),
+            Op(vec![Skip(1), Delete(2)]),
+        ];
+        let (op, positions) = compact_burst_with_positions(&ops);
+        assert_eq!(op, compact_burst(&ops));
+        assert_eq!(applied(&b"abc".to_vec(), &op), b"xbc");
+        // The 'c' in "abc", "xyabc" and "xbc".
+        assert_eq!(positions.map(0, 2, Gravity::Before), Some(2));
+        assert_eq!(positions.map(1, 4, Gravity::Before), Some(2));
+        assert_eq!(positions.map(2, 2, Gravity::Before), Some(2));
+        // The deleted 'a'.
+        assert_eq!(positions.map(1, 2, Gravity::After), Some(1));
+        assert_eq!(positions.map(0, 0, Gravity::After), Some(1));
+        assert_eq!(positions.map(0, 0, Gravity::Before), Some(0));
+        // Past the last version.
+        assert_eq!(positions.map(3, 0, Gravity::Before), None);
+    }
+
+    #[test]
+    fn test_apply_budgeted() {
+        let doc: Doc = (0..10_000).map(|i| (i % 26) as u8 + b'a').collect();
+        let op = vec![
+            Skip(100),
+            Insert(vec![b'x'; 2500]),
+            Delete(1500),
+            Skip(3000),
+            Delete(0),
+            Insert(b"yz".to_vec()),
+            Skip(10),
+            Delete(999),
+        ];
+        let mut expected = doc.clone();
+        apply(&mut expected, &op);
+
+        let mut budgeted = doc.clone();
+        let mut cursor = ApplyCursor::default();
+        let mut calls = 1;
+        while apply_budgeted(&mut budgeted, &op, &mut cursor, 1000) == ApplyProgress::MoreRemaining
+        {
+            calls += 1;
+        }
+        assert_eq!(budgeted, expected);
+        // 2500 + 1500 + 2 + 999 bytes.
+        assert_eq!(calls, 6);
+        assert_eq!(
+            apply_budgeted(&mut budgeted, &op, &mut cursor, 1000),
+            ApplyProgress::Done
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "budget must be positive")]
+    fn test_apply_budgeted_zero_budget() {
+        let mut doc = b"abc".to_vec();
+        apply_budgeted(&mut doc, &[Delete(1)], &mut ApplyCursor::default(), 0);
+    }
+
+    #[test]
+    fn test_apply_into() {
+        let src = b"abcdef".to_vec();
+        let op = vec![
+            Skip(1),
+            Delete(2),
+            Insert(b"xyz".to_vec()),
+            Skip(1),
+            Delete(1),
+        ];
+        let mut expected = src.clone();
+        apply(&mut expected, &op);
+
+        let mut dst = vec![];
+        apply_into(&src, &op, &mut dst);
+        assert_eq!(dst, expected);
+        assert_eq!(dst.capacity(), expected.len());
+        assert_eq!(src, b"abcdef");
+
+        // Reuses the buffer, replacing its contents.
+        apply_into(&src, &[], &mut dst);
+        assert_eq!(dst, src);
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let mut doc = b"abcdef".to_vec();
+        let changes = apply_changes(
+            &mut doc,
+            &[
+                Skip(1),
+                Delete(2),
+                Insert(b"xy".to_vec()),
+                Skip(2),
+                Delete(1),
+            ],
+        );
+        assert_eq!(doc, b"axyde");
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    pos: 1,
+                    removed: b"bc".to_vec(),
+                    inserted: b"xy".to_vec(),
+                },
+                Change {
+                    pos: 5,
+                    removed: b"f".to_vec(),
+                    inserted: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tags_survive_transform() {
+        let mut doc = b"abc".to_vec();
+        let local = vec![Insert(b"xy".to_vec()), Skip(1), Delete(1)];
+        apply_with_scratch(&mut doc, &local, &mut vec![]);
+
+        // Someone else's inserts, shifted past the local insert and brought together by the
+        // local delete.
+        let remote = TaggedOp(vec![
+            TaggedStep::Skip(1),
+            TaggedStep::Insert(b"z".to_vec(), Some(7)),
+            TaggedStep::Skip(1),
+            TaggedStep::Insert(b"w".to_vec(), Some(8)),
+        ]);
+        let remote = transform_tagged(&remote, &local, Right);
+        assert_eq!(
+            remote,
+            TaggedOp(vec![
+                TaggedStep::Skip(3),
+                TaggedStep::Insert(b"z".to_vec(), Some(7)),
+                TaggedStep::Insert(b"w".to_vec(), Some(8)),
+            ])
+        );
+        assert_eq!(
+            apply_observed(&mut doc, &remote),
+            vec![
+                TaggedChange {
+                    change: Change {
+                        pos: 3,
+                        removed: vec![],
+                        inserted: b"z".to_vec(),
+                    },
+                    tag: Some(7),
+                },
+                TaggedChange {
+                    change: Change {
+                        pos: 4,
+                        removed: vec![],
+                        inserted: b"w".to_vec(),
+                    },
+                    tag: Some(8),
+                },
+            ]
+        );
+        assert_eq!(doc, b"xyazwc");
+    }
+
+    #[test]
+    fn test_transform() {
+        let op1 = vec![Skip(1), Delete(2), Insert(b"x".to_vec())];
+        let op2 = vec![Skip(2), Insert(b"yz".to_vec())];
+        assert_eq!(
+            transform(&op1, &op2, Left),
+            vec![
+                Skip(1),
+                Delete(1),
+                Skip(2),
+                Insert(b"x".to_vec()),
+                Delete(1)
+            ]
+        );
+        assert_eq!(
+            transform(&op2, &op1, Right),
+            vec![Skip(1), Insert(b"yz".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_transform_delete_spanning_inserts() {
+        let doc = b"abcdefgh".to_vec();
+        // Deletes "bcdefg", which `op2` inserts into twice.
+        let op1 = vec![Skip(1), Delete(6)];
+        let op2 = vec![
+            Skip(2),
+            Insert(b"XY".to_vec()),
+            Skip(3),
+            Insert(b"Z".to_vec()),
+        ];
+        for &side in &[Left, Right] {
+            let op1_prime = transform(&op1, &op2, side);
+            assert_eq!(
+                op1_prime,
+                vec![Skip(1), Delete(1), Skip(2), Delete(3), Skip(1), Delete(2)]
+            );
+            assert_eq!(applied(&applied(&doc, &op2), &op1_prime), b"aXYZh");
+        }
+        let op2_prime = transform(&op2, &op1, Right);
+        assert_eq!(op2_prime, vec![Skip(1), Insert(b"XYZ".to_vec())]);
+        assert_eq!(applied(&applied(&doc, &op1), &op2_prime), b"aXYZh");
+    }
+
+    #[test]
+    fn test_transform_insert_tie() {
+        let op1 = vec![Skip(1), Insert(b"a".to_vec())];
+        let op2 = vec![Skip(1), Insert(b"b".to_vec())];
+        assert_eq!(
+            transform(&op1, &op2, Left),
+            vec![Skip(1), Insert(b"a".to_vec())]
+        );
+        assert_eq!(
+            transform(&op1, &op2, Right),
+            vec![Skip(2), Insert(b"a".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_selection_only_op() {
+        let selection = vec![Skip(4), Skip(0)];
+        let op2 = vec![Skip(1), Delete(2)];
+        assert_eq!(transform(&selection, &op2, Left), selection);
+        assert_eq!(transform_content_ordered(&selection, &op2), selection);
+
+        let mut doc = applied(&b"abcd".to_vec(), &op2);
+        apply_with_scratch(&mut doc, &selection, &mut vec![]);
+        assert_eq!(doc, b"ad");
+        assert_eq!(apply_changes(&mut doc, &selection), vec![]);
         assert_eq!(doc, b"ad");
     }
+
+    #[test]
+    fn test_transform_append() {
+        let op1 = vec![Skip(3), Insert(b"x".to_vec())];
+        let op2 = vec![Skip(3), Insert(b"yz".to_vec())];
+        assert_eq!(transform_append(&op1, &op2, Left), op1);
+        assert_eq!(
+            transform_append(&op1, &op2, Right),
+            vec![Skip(5), Insert(b"x".to_vec())]
+        );
+        assert_eq!(
+            transform_append(
+                &vec![Insert(b"x".to_vec())],
+                &vec![Insert(b"y".to_vec())],
+                Right
+            ),
+            vec![Skip(1), Insert(b"x".to_vec())]
+        );
+        // Not an append.
+        assert_eq!(
+            transform_append(&op1, &vec![Delete(1)], Left),
+            vec![Skip(2), Insert(b"x".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_transform_ranged() {
+        let ops: Vec<RangedOp> = vec![
+            vec![Skip(2), Insert(b"xy".to_vec())],
+            vec![Skip(1), Delete(3)],
+            vec![Skip(6), Delete(1), Insert(b"z".to_vec())],
+            vec![Skip(9), Insert(b"w".to_vec()), Skip(1), Delete(2)],
+            vec![],
+        ]
+        .into_iter()
+        .map(|op| normalize(&op).into())
+        .collect();
+        for op1 in &ops {
+            for op2 in &ops {
+                let slow = transform(&op1.op, &op2.op, Left);
+                let fast = transform_ranged(op1, op2, Left);
+                assert_eq!(fast.op, slow, "{:?} against {:?}", op1, op2);
+                assert_eq!(fast.affected, RangedOp::from(slow).affected);
+            }
+        }
+        assert!(transform_disjoint(&ops[0], &ops[2]).is_some());
+        assert!(transform_disjoint(&ops[3], &ops[1]).is_some());
+        assert!(transform_disjoint(&ops[0], &ops[1]).is_none());
+    }
+
+    #[test]
+    fn test_transform_with_conflict_info() {
+        let op1 = vec![Skip(2), Delete(3)];
+        assert_eq!(
+            transform_with_conflict_info(&op1, &vec![Delete(1), Skip(5), Delete(1)], Left),
+            (Op(vec![Skip(1), Delete(3)]), ConflictInfo::Untouched)
+        );
+        assert_eq!(
+            transform_with_conflict_info(
+                &op1,
+                &vec![Skip(1), Delete(2), Insert(b"x".to_vec())],
+                Left
+            ),
+            (
+                Op(vec![Skip(2), Delete(2)]),
+                ConflictInfo::PartiallyCancelled
+            )
+        );
+        assert_eq!(
+            transform_with_conflict_info(&op1, &vec![Skip(1), Delete(5)], Left),
+            (Op::default(), ConflictInfo::FullyCancelled)
+        );
+        assert_eq!(
+            transform_with_conflict_info(&vec![Insert(b"x".to_vec())], &op1, Left),
+            (Op(vec![Insert(b"x".to_vec())]), ConflictInfo::Untouched)
+        );
+    }
+
+    #[test]
+    fn test_transform_checked() {
+        let op1 = vec![Skip(3), Insert(b"x".to_vec())];
+        assert_eq!(
+            transform_checked(&op1, &vec![Delete(1)], Left, 3),
+            Ok(Op(vec![Skip(2), Insert(b"x".to_vec())]))
+        );
+        // Built on a document with two more bytes.
+        assert_eq!(
+            transform_checked(&op1, &vec![Skip(4), Delete(1)], Left, 3),
+            Err(ApplyError::OutOfBounds {
+                input_len: 5,
+                doc_len: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_transform_lenient() {
+        let op1 = vec![Skip(3), Insert(b"x".to_vec())];
+        let op2 = vec![Skip(1), Delete(5)];
+        assert!(transform_checked(&op1, &op2, Left, 3).is_err());
+
+        let op1_prime = transform_lenient(&op1, &op2, Left, 3);
+        assert_eq!(op1_prime, vec![Skip(1), Insert(b"x".to_vec())]);
+        // Usable on the document after the part of `op2` that fits.
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &clamp_to_len(&op2, 3));
+        assert_eq!(doc, b"a");
+        assert_eq!(try_apply(&mut doc, &op1_prime), Ok(()));
+        assert_eq!(doc, b"ax");
+
+        assert_eq!(
+            clamp_to_len(&[Skip(2), Delete(3), Insert(b"y".to_vec()), Skip(1)], 4),
+            vec![Skip(2), Insert(b"y".to_vec()), Delete(2)]
+        );
+    }
+
+    #[test]
+    fn test_transform_content_ordered() {
+        let op1 = vec![Skip(1), Insert(b"b".to_vec())];
+        let op2 = vec![Skip(1), Insert(b"ab".to_vec())];
+        assert_eq!(converged_content_ordered(b"xy", &op1, &op2), b"xabby");
+        assert_eq!(converged_content_ordered(b"xy", &op2, &op1), b"xabby");
+    }
+
+    fn converged_content_ordered(doc: &[u8], op1: &[Step], op2: &[Step]) -> Doc {
+        let doc1 = applied(
+            &applied(&doc.to_vec(), op1),
+            &transform_content_ordered(op2, op1),
+        );
+        let doc2 = applied(
+            &applied(&doc.to_vec(), op2),
+            &transform_content_ordered(op1, op2),
+        );
+        assert_eq!(doc1, doc2);
+        doc1
+    }
+
+    #[test]
+    fn test_transform_by_site_three_way_tie() {
+        // Three sites insert at the same position concurrently.
+        let ops: Vec<(u32, Vec<Step>)> = vec![
+            (2, vec![Skip(1), Insert(b"z".to_vec())]),
+            (0, vec![Skip(1), Insert(b"x".to_vec())]),
+            (1, vec![Skip(1), Insert(b"y".to_vec())]),
+        ];
+        let orders = [
+            [0, 1, 2],
+            [0, 2, 1],
+            [1, 0, 2],
+            [1, 2, 0],
+            [2, 0, 1],
+            [2, 1, 0],
+        ];
+        for order in &orders {
+            let mut doc = b"ab".to_vec();
+            // The ops applied so far, each transformed against the ones before it.
+            let mut integrated: Vec<(u32, Op)> = vec![];
+            for &i in order {
+                let (site, ref op) = ops[i];
+                let op = integrated
+                    .iter()
+                    .fold(Op(op.clone()), |op, (other, other_op)| {
+                        transform_by_site(&op, site, other_op, *other)
+                    });
+                doc = applied(&doc, &op);
+                integrated.push((site, op));
+            }
+            assert_eq!(doc, b"axyzb", "order = {:?}", order);
+        }
+    }
+
+    #[test]
+    fn test_transform_huge_lengths() {
+        let max = usize::MAX;
+        let op1 = vec![Skip(max), Skip(1), Delete(max)];
+        let op2 = vec![Delete(max), Delete(max), Insert(b"x".to_vec())];
+        assert_eq!(transform(&op1, &op2, Left), vec![Skip(1), Delete(1)]);
+        assert_eq!(
+            transform(&op2, &op1, Right),
+            vec![Insert(b"x".to_vec()), Delete(max)]
+        );
+        assert_eq!(
+            normalize(&[Skip(max), Skip(1), Delete(1)]),
+            vec![Skip(max), Delete(1)]
+        );
+    }
+
+    #[test]
+    fn test_transform_against_identity() {
+        let op1 = vec![Skip(1), Insert(b"x".to_vec()), Delete(2)];
+        for op2 in &[vec![], vec![Skip(3)], vec![Skip(1), Delete(0), Skip(2)]] {
+            assert!(is_identity(op2));
+            assert_eq!(transform(&op1, op2, Left), op1);
+            assert_eq!(transform_steps(&op1, op2, |_, _, _| true), op1);
+            assert_eq!(transform_steps(&op1, op2, |_, _, _| false), op1);
+        }
+        assert!(!is_identity(&op1));
+    }
+
+    fn converged(doc: &[u8], op1: &[Step], op2: &[Step]) -> Doc {
+        let mut doc1 = doc.to_vec();
+        apply_with_scratch(&mut doc1, op1, &mut vec![]);
+        apply_with_scratch(&mut doc1, &transform(op2, op1, Right), &mut vec![]);
+        let mut doc2 = doc.to_vec();
+        apply_with_scratch(&mut doc2, op2, &mut vec![]);
+        apply_with_scratch(&mut doc2, &transform(op1, op2, Left), &mut vec![]);
+        assert_eq!(doc1, doc2);
+        doc1
+    }
+
+    #[test]
+    fn test_insert_inside_concurrent_delete() {
+        // op2 deletes "bcd"; op1's insert survives at the deletion point.
+        let op2 = vec![Skip(1), Delete(3)];
+        for (pos, expected) in &[(1, &b"axe"[..]), (2, b"axe"), (3, b"axe"), (4, b"axe")] {
+            let op1 = vec![Skip(*pos), Insert(b"x".to_vec())];
+            assert_eq!(
+                transform(&op1, &op2, Left),
+                vec![Skip(1), Insert(b"x".to_vec())]
+            );
+            assert_eq!(converged(b"abcde", &op1, &op2), *expected);
+        }
+        assert_eq!(
+            transform(&op2, &vec![Skip(2), Insert(b"x".to_vec())], Right),
+            vec![Skip(1), Delete(1), Skip(1), Delete(2)]
+        );
+    }
+
+    #[test]
+    fn test_insert_vs_delete_around_position() {
+        let insert = vec![Skip(2), Insert(b"x".to_vec())];
+        for (start, expected) in &[(1, &b"axcd"[..]), (2, b"abxd"), (3, b"abxc")] {
+            let delete = vec![Skip(*start), Delete(1)];
+            assert_eq!(converged(b"abcd", &insert, &delete), *expected);
+            assert_eq!(converged(b"abcd", &delete, &insert), *expected);
+        }
+        assert_eq!(
+            transform(&insert, &[Skip(1), Delete(1)], Left),
+            vec![Skip(1), Insert(b"x".to_vec())]
+        );
+        assert_eq!(transform(&insert, &[Skip(2), Delete(1)], Left), insert);
+        assert_eq!(
+            transform(&[Skip(2), Delete(1)], &insert, Left),
+            vec![Skip(3), Delete(1)]
+        );
+    }
+
+    #[test]
+    fn test_splice() {
+        assert_eq!(splice(2, 0, b"xy"), vec![Skip(2), Insert(b"xy".to_vec())]);
+        assert_eq!(splice(2, 3, b""), vec![Skip(2), Delete(3)]);
+        assert_eq!(splice(0, 0, b""), vec![]);
+
+        let op = splice(1, 2, b"xyz");
+        assert_eq!(op, vec![Skip(1), Insert(b"xyz".to_vec()), Delete(2)]);
+        assert_eq!(applied(&b"abcd".to_vec(), &op), b"axyzd");
+    }
+
+    #[test]
+    fn test_wrap_range() {
+        let op = wrap_range(1, 3, b"(", b")");
+        assert_eq!(
+            op,
+            vec![
+                Skip(1),
+                Insert(b"(".to_vec()),
+                Skip(2),
+                Insert(b")".to_vec())
+            ]
+        );
+        assert_eq!(applied(&b"abcd".to_vec(), &op), b"a(bc)d");
+        assert_eq!(
+            wrap_range(2, 2, b"\"", b"\""),
+            vec![Skip(2), Insert(b"\"\"".to_vec())]
+        );
+
+        let insert = vec![Skip(2), Insert(b"x".to_vec())];
+        assert_eq!(converged(b"abcd", &op, &insert), b"a(bxc)d");
+        let delete = vec![Delete(2)];
+        assert_eq!(converged(b"abcd", &op, &delete), b"(c)d");
+    }
+
+    #[test]
+    #[should_panic(expected = "start 3 > end 1")]
+    fn test_wrap_range_reversed() {
+        wrap_range(3, 1, b"(", b")");
+    }
+
+    #[test]
+    fn test_transform_position() {
+        use Gravity::*;
+        let op = vec![Skip(2), Insert(b"xy".to_vec()), Skip(1), Delete(3)];
+        for &gravity in &[Before, After] {
+            assert_eq!(transform_position(1, &op, gravity), 1);
+            // Pinned inside the deletion (or at its first byte): clamps to its start.
+            for pos in 3..6 {
+                assert_eq!(transform_position(pos, &op, gravity), 5);
+            }
+            assert_eq!(transform_position(6, &op, gravity), 5);
+            assert_eq!(transform_position(9, &op, gravity), 8);
+        }
+        assert_eq!(transform_position(2, &op, Before), 2);
+        assert_eq!(transform_position(2, &op, After), 4);
+
+        // A replacement: the pin clamps after the replacing text, unless it's at its very start.
+        let op = splice(1, 3, b"xyz");
+        assert_eq!(transform_position(1, &op, Before), 1);
+        assert_eq!(transform_position(1, &op, After), 4);
+        assert_eq!(transform_position(2, &op, Before), 4);
+        assert_eq!(transform_position(2, &op, After), 4);
+    }
+
+    #[test]
+    fn test_transformable() {
+        // Inserts at 2 and at 4, deletes 5..7.
+        let op = Op(vec![
+            Skip(2),
+            Insert(b"ab".to_vec()),
+            Skip(2),
+            Insert(b"c".to_vec()),
+            Skip(1),
+            Delete(2),
+        ]);
+
+        assert_eq!(1.transform_through(&op, Left), 1);
+        assert_eq!(2.transform_through(&op, Left), 2);
+        assert_eq!(2.transform_through(&op, Right), 4);
+        assert_eq!(6.transform_through(&op, Right), 8);
+        assert_eq!(9.transform_through(&op, Left), 10);
+
+        // The inserts at both ends stay outside the selection, whatever the side.
+        for &side in &[Left, Right] {
+            assert_eq!((2..4).transform_through(&op, side), 4..6);
+            assert_eq!((3..6).transform_through(&op, side), 5..8);
+        }
+        assert_eq!((2..2).transform_through(&op, Left), 2..2);
+        assert_eq!((2..2).transform_through(&op, Right), 4..4);
+        // A selection inside the deleted text collapses.
+        assert_eq!((5..7).transform_through(&op, Left), 8..8);
+
+        let anchor = |pos, gravity| Anchor { pos, gravity };
+        assert_eq!(
+            anchor(4, Gravity::Before).transform_through(&op, Right),
+            anchor(6, Gravity::Before)
+        );
+        assert_eq!(
+            anchor(4, Gravity::After).transform_through(&op, Left),
+            anchor(7, Gravity::After)
+        );
+    }
+
+    #[test]
+    fn test_reverse_range() {
+        let doc = b"abcdefg".to_vec();
+        let op = reverse_range(&doc, 1, 6);
+        assert_eq!(applied(&doc, &op), b"afedcbg");
+        assert_eq!(reverse_range(&doc, 3, 3), vec![]);
+
+        // A concurrent insert inside the range is kept next to the reversed text.
+        let insert = vec![Skip(3), Insert(b"x".to_vec())];
+        assert_eq!(converged(&doc, &op, &insert), b"afedcbxg");
+    }
+
+    #[test]
+    fn test_insert_from_byte_likes() {
+        let expected = vec![Skip(1), Insert(b"xy".to_vec())];
+        assert_eq!(vec![Skip(1), Step::insert("xy")], expected);
+        assert_eq!(vec![Skip(1), Step::insert(&b"xy"[..])], expected);
+        assert_eq!(vec![Skip(1), Step::insert(b"xy".to_vec())], expected);
+        assert_eq!(splice(1, 0, "xy".to_string()), expected);
+        assert_eq!(splice(1, 0, &b"xy"[..]), expected);
+    }
+
+    #[test]
+    fn test_clear_vs_insert() {
+        let clear = clear_op(3);
+        assert_eq!(clear, vec![Delete(3)]);
+        let insert = vec![Skip(1), Insert(b"x".to_vec())];
+        assert_eq!(converged(b"abc", &clear, &insert), b"x");
+        assert_eq!(converged(b"abc", &insert, &clear), b"x");
+    }
+
+    #[test]
+    fn test_clear_vs_delete() {
+        let clear = clear_op(3);
+        let delete = vec![Skip(1), Delete(1)];
+        assert_eq!(transform(&delete, &clear, Left), vec![]);
+        assert_eq!(converged(b"abc", &clear, &delete), b"");
+        assert_eq!(converged(b"abc", &delete, &clear), b"");
+        assert_eq!(clear_op(0), vec![]);
+    }
+
+    #[test]
+    fn test_insert_tiebreak() {
+        assert_eq!(insert_tiebreak(1, 2, Left), Less);
+        assert_eq!(insert_tiebreak(1, 2, Right), Less);
+        assert_eq!(insert_tiebreak(2, 2, Left), Less);
+        assert_eq!(insert_tiebreak(2, 2, Right), Greater);
+        assert_eq!(insert_tiebreak(3, 2, Left), Greater);
+        assert_eq!(insert_tiebreak(3, 2, Right), Greater);
+    }
+
+    #[test]
+    fn test_identity() {
+        let identity = Op::identity();
+        assert!(is_identity(&identity));
+        let op = normalize(&[Skip(1), Insert(b"x".to_vec()), Skip(1), Delete(2)]);
+        assert_eq!(compose(&identity, &op), op);
+        assert_eq!(compose(&op, &identity), op);
+        assert_eq!(transform(&op, &identity, Left), op);
+        assert_eq!(transform(&identity, &op, Left), identity);
+
+        let mut doc = b"abcd".to_vec();
+        apply(&mut doc, &identity);
+        assert_eq!(doc, b"abcd");
+    }
+
+    #[test]
+    fn test_initial_op() {
+        let op = initial_op(b"hello");
+        assert_eq!(op, vec![Insert(b"hello".to_vec())]);
+        assert_eq!(applied(&vec![], &op), b"hello");
+        assert_eq!(initial_op(b""), vec![]);
+
+        let other = vec![Insert(b"x".to_vec())];
+        assert_eq!(converged(b"", &op, &other), b"hellox");
+        assert_eq!(converged(b"", &other, &op), b"xhello");
+    }
+
+    fn inserted_len(op: &[Step]) -> usize {
+        op.iter()
+            .map(|step| match step {
+                Insert(s) => s.len(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_split_by_size() {
+        let text: Chunk = (0..3000).map(|i| (i % 26) as u8 + b'a').collect();
+        let op = vec![Skip(5), Insert(text.clone())];
+        let ops = split_by_size(&op, 1200);
+        assert_eq!(ops.len(), 3);
+        assert_eq!(
+            ops.iter().map(|op| inserted_len(op)).collect::<Vec<_>>(),
+            vec![1200, 1200, 600]
+        );
+        assert_eq!(ops[1][0], Skip(1205));
+
+        let mut expected = b"hello world".to_vec();
+        apply(&mut expected, &op);
+        let mut doc = b"hello world".to_vec();
+        for op in &ops {
+            apply(&mut doc, op);
+        }
+        assert_eq!(doc, expected);
+    }
+
+    #[test]
+    fn test_split_by_size_with_delete() {
+        let op = vec![Delete(1), Insert(vec![b'x'; 1500]), Skip(2), Delete(3)];
+        let ops = split_by_size(&op, 1000);
+        assert_eq!(
+            ops,
+            vec![
+                vec![Insert(vec![b'x'; 1000]), Delete(1)],
+                vec![Skip(1000), Insert(vec![b'x'; 500]), Skip(2), Delete(3)],
+            ]
+        );
+        assert_eq!(
+            split_by_size(&[Skip(2), Delete(1)], 10),
+            vec![vec![Skip(2), Delete(1)]]
+        );
+    }
+
+    #[test]
+    fn test_affected_range() {
+        assert_eq!(affected_range(&[Skip(3)]), None);
+        assert_eq!(
+            affected_range(&[Skip(1), Insert(b"ab".to_vec())]),
+            Some(1..1)
+        );
+        assert_eq!(
+            affected_range(&[Skip(1), Delete(2), Skip(3), Insert(b"x".to_vec()), Skip(1)]),
+            Some(1..6)
+        );
+    }
+
+    use crate::test_support::format_divergence;
+    use proptest::prelude::*;
+
+    fn valid_op_for(len: usize) -> impl Strategy<Value = Op> {
+        proptest::collection::vec((0..3u8, 1..5usize, any::<Chunk>()), 0..8).prop_map(
+            move |steps| {
+                let mut remaining = len;
+                let mut op = vec![];
+                for (kind, n, s) in steps {
+                    let n = min(n, remaining);
+                    match kind {
+                        0 if n > 0 => {
+                            remaining -= n;
+                            op.push(Skip(n));
+                        }
+                        1 if n > 0 => {
+                            remaining -= n;
+                            op.push(Delete(n));
+                        }
+                        _ => op.push(Insert(s)),
+                    }
+                }
+                Op(op)
+            },
+        )
+    }
+
+    fn applied(doc: &Doc, op: &[Step]) -> Doc {
+        let mut doc = doc.clone();
+        apply_with_scratch(&mut doc, op, &mut vec![]);
+        doc
+    }
+
+    /// A document, an op `x` and an op `a` on it, and an op `b` on the document after `a`.
+    fn without_inserts(op: Op) -> Op {
+        op.0.into_iter()
+            .filter(|step| !matches!(step, Insert(_)))
+            .collect()
+    }
+
+    fn without_deletes(op: Op) -> Op {
+        op.0.into_iter()
+            .map(|step| match step {
+                Delete(n) => Skip(n),
+                step => step,
+            })
+            .collect()
+    }
+
+    /// A document, an op `x` on it and two consecutive ops `a` and `b` from it, in the shapes for
+    /// which transforming against `compose(a, b)` is transforming against `a` and `b` (see
+    /// `transform`): `x` only deletes, or `a` and `b` both only insert or both only delete.
+    fn doc_and_ops_for_compose_law() -> impl Strategy<Value = (Doc, Op, Op, Op)> {
+        (any::<Doc>(), 0..3u8)
+            .prop_flat_map(|(doc, shape)| {
+                let len = doc.len();
+                (Just(doc), Just(shape), valid_op_for(len), valid_op_for(len))
+            })
+            .prop_flat_map(|(doc, shape, x, a)| {
+                let (x, a) = match shape {
+                    0 => (without_inserts(x), a),
+                    1 => (x, without_deletes(a)),
+                    _ => (x, without_inserts(a)),
+                };
+                let len = applied(&doc, &a).len();
+                let b = valid_op_for(len).prop_map(move |b| match shape {
+                    0 => b,
+                    1 => without_deletes(b),
+                    _ => without_inserts(b),
+                });
+                (Just(doc), Just(x), Just(a), b)
+            })
+    }
+
+    fn doc_and_two_concurrent_ops() -> impl Strategy<Value = (Doc, Op, Op)> {
+        any::<Doc>().prop_flat_map(|doc| {
+            (valid_op_for(doc.len()), valid_op_for(doc.len()))
+                .prop_map(move |(op1, op2)| (doc.clone(), op1, op2))
+        })
+    }
+
+    fn doc_and_two_consecutive_ops() -> impl Strategy<Value = (Doc, Op, Op)> {
+        any::<Doc>()
+            .prop_flat_map(|doc| (valid_op_for(doc.len()), Just(doc)))
+            .prop_flat_map(|(op1, doc)| {
+                let len = applied(&doc, &op1).len();
+                (Just(doc), Just(op1), valid_op_for(len))
+            })
+    }
+
+    fn arb_step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            any::<usize>().prop_map(Skip),
+            any::<Chunk>().prop_map(Insert),
+            any::<usize>().prop_map(Delete),
+        ]
+    }
+
+    /// Input positions of the op's inserts.
+    fn insert_positions(op: &[Step]) -> Vec<usize> {
+        let mut pos = 0;
+        let mut positions = vec![];
+        for step in op {
+            match step {
+                Insert(s) if !s.is_empty() => positions.push(pos),
+                Insert(_) => {}
+                Skip(n) | Delete(n) => pos += n,
+            }
+        }
+        positions
+    }
+
+    /// Input ranges deleted by the op, merged where adjacent.
+    fn deleted_ranges(op: &[Step]) -> Vec<Range<usize>> {
+        let mut pos = 0;
+        let mut ranges: Vec<Range<usize>> = vec![];
+        for step in op {
+            match step {
+                Skip(n) => pos += n,
+                Delete(n) if *n > 0 => {
+                    match ranges.last_mut() {
+                        Some(range) if range.end == pos => range.end += n,
+                        _ => ranges.push(pos..pos + n),
+                    }
+                    pos += n;
+                }
+                _ => {}
+            }
+        }
+        ranges
+    }
+
+    proptest! {
+        #[test]
+        fn side_only_affects_insert_ties((_, op1, op2) in doc_and_two_concurrent_ops()) {
+            let left = transform(&op1, &op2, Left);
+            let right = transform(&op1, &op2, Right);
+            prop_assert_eq!(deleted_ranges(&left), deleted_ranges(&right));
+            let positions2 = insert_positions(&op2);
+            if insert_positions(&op1).iter().all(|pos| !positions2.contains(pos)) {
+                prop_assert_eq!(left, right);
+            }
+        }
+
+        #[test]
+        fn transform_arbitrary_ops_doesnt_panic(
+            op1 in proptest::collection::vec(arb_step(), 0..6),
+            op2 in proptest::collection::vec(arb_step(), 0..6),
+        ) {
+            transform(&op1, &op2, Left);
+            transform(&op1, &op2, Right);
+        }
+
+        #[test]
+        fn specialized_transforms_match_general((_, op1, op2) in doc_and_two_concurrent_ops()) {
+            let inserts_only: Op = op2
+                .iter()
+                .map(|step| match step {
+                    Delete(n) => Skip(*n),
+                    step => step.clone(),
+                })
+                .collect();
+            let deletes_only: Op = op2.iter().filter(|step| !matches!(step, Insert(_))).cloned().collect();
+            for &side in &[Left, Right] {
+                let mut result: Vec<Step> = vec![];
+                transform_against_inserts(&op1, &inserts_only, side, &mut result);
+                prop_assert_eq!(result, transform_steps(&op1, &inserts_only, |_, _, _| side == Left).0);
+                // Up to normalization, because of the fast path for identity ops.
+                prop_assert_eq!(
+                    normalize(&transform(&op1, &inserts_only, side)),
+                    transform_steps(&op1, &inserts_only, |_, _, _| side == Left)
+                );
+            }
+            let mut result: Vec<Step> = vec![];
+            transform_against_deletes(&op1, &deletes_only, &mut result);
+            prop_assert_eq!(result, transform_steps(&op1, &deletes_only, |_, _, _| true).0);
+        }
+
+        #[test]
+        fn compose_with_inverse_is_identity((doc, op, _) in doc_and_two_concurrent_ops()) {
+            prop_assert!(is_identity_after_compose(&op, &doc));
+            let mut result = applied(&doc, &op);
+            result = applied(&result, &invert(&op, &doc));
+            prop_assert_eq!(result, doc);
+        }
+
+        #[test]
+        fn transform_against_compose_is_transform_against_each(
+            (_, x, a, b) in doc_and_ops_for_compose_law(),
+            left in any::<bool>(),
+        ) {
+            let side = if left { Left } else { Right };
+            // Unnormalized, an insert at the end of a delete ties like one at its start.
+            let x = normalize(&x);
+            prop_assert_eq!(
+                normalize(&transform(&x, &compose(&a, &b), side)),
+                normalize(&transform(&transform(&x, &a, side), &b, side))
+            );
+        }
+
+        #[test]
+        fn transform_in_place_matches_transform((_, op1, op2) in doc_and_two_concurrent_ops()) {
+            let mut scratch = vec![];
+            for &side in &[Left, Right] {
+                let expected = transform(&op1, &op2, side);
+                let mut op = op1.clone();
+                transform_in_place_with_scratch(&mut op, &op2, side, &mut scratch);
+                prop_assert_eq!(op, expected);
+            }
+            let mut op = op1.clone();
+            transform_in_place(&mut op, &op2, Right);
+            prop_assert_eq!(op, transform(&op1, &op2, Right));
+        }
+
+        #[test]
+        fn apply_matches_apply_with_scratch((doc, op) in any::<Doc>().prop_flat_map(|doc| (valid_op_for(doc.len()), Just(doc)))
+            .prop_map(|(op, doc)| (doc, op))) {
+            let mut in_place = doc.clone();
+            apply(&mut in_place, &op);
+            prop_assert_eq!(in_place, applied(&doc, &op));
+        }
+
+        #[test]
+        fn transform_borrowed_matches_transform((_, op1, op2) in doc_and_two_concurrent_ops()) {
+            for &side in &[Left, Right] {
+                prop_assert_eq!(transform_borrowed(&op1, &op2, side).to_op(), transform(&op1, &op2, side));
+            }
+        }
+
+        #[test]
+        fn split_by_size_is_sequential((doc, op, _) in doc_and_two_concurrent_ops(), max_bytes in 1..10usize) {
+            let ops = split_by_size(&op, max_bytes);
+            let doc_after_split = ops.iter().fold(doc.clone(), |doc, op| applied(&doc, op));
+            prop_assert_eq!(doc_after_split, applied(&doc, &op));
+            prop_assert!(ops.iter().all(|op| inserted_len(op) <= max_bytes));
+        }
+
+        #[test]
+        fn transform_property_1((doc, op1, op2) in doc_and_two_concurrent_ops()) {
+            let transformed_op2 = transform(&op2, &op1, Right);
+            let doc1 = applied(&applied(&doc, &op1), &transformed_op2);
+
+            let transformed_op1 = transform(&op1, &op2, Left);
+            let doc2 = applied(&applied(&doc, &op2), &transformed_op1);
+
+            prop_assert_eq!(
+                &doc1,
+                &doc2,
+                "\n{}\ntransformed_op1 = {:?},\ntransformed_op2 = {:?}\n",
+                format_divergence(&doc1, &doc2),
+                transformed_op1,
+                transformed_op2
+            );
+        }
+
+        #[test]
+        fn transform_content_ordered_converges((doc, op1, op2) in doc_and_two_concurrent_ops()) {
+            let doc1 = applied(&applied(&doc, &op1), &transform_content_ordered(&op2, &op1));
+            let doc2 = applied(&applied(&doc, &op2), &transform_content_ordered(&op1, &op2));
+            prop_assert_eq!(&doc1, &doc2, "\n{}", format_divergence(&doc1, &doc2));
+        }
+
+        #[test]
+        fn transform_append_converges(
+            doc in any::<Doc>(),
+            entry1 in any::<Chunk>(),
+            entry2 in any::<Chunk>(),
+        ) {
+            let op1 = normalize(&[Skip(doc.len()), Insert(entry1)]);
+            let op2 = normalize(&[Skip(doc.len()), Insert(entry2)]);
+            let transformed_op2 = transform_append(&op2, &op1, Right);
+            let transformed_op1 = transform_append(&op1, &op2, Left);
+            prop_assert_eq!(&transformed_op2, &transform(&op2, &op1, Right));
+            prop_assert_eq!(&transformed_op1, &transform(&op1, &op2, Left));
+            prop_assert_eq!(
+                applied(&applied(&doc, &op1), &transformed_op2),
+                applied(&applied(&doc, &op2), &transformed_op1)
+            );
+        }
+
+        #[test]
+        fn compose_is_sequential_apply((doc, op1, op2) in doc_and_two_consecutive_ops()) {
+            let composed = compose(&op1, &op2);
+            prop_assert_eq!(applied(&doc, &composed), applied(&applied(&doc, &op1), &op2));
+            prop_assert_eq!(applied(&doc, &normalize(&composed)), applied(&doc, &composed));
+        }
+
+        #[test]
+        fn transform_against_later_range_is_noop((_, op1, op2) in doc_and_two_concurrent_ops(), left in any::<bool>()) {
+            let side = if left { Left } else { Right };
+            if let (Some(range1), Some(range2)) = (affected_range(&op1), affected_range(&op2)) {
+                prop_assume!(range2.start > range1.end);
+            }
+            prop_assert_eq!(normalize(&transform(&op1, &op2, side)), normalize(&op1));
+        }
+    }
 }
@@ -0,0 +1,447 @@
+//! Diffing documents into ops, and three-way merge built on top of it.
+
+use crate::composite_op::{
+    apply_with_scratch, normalize, transform, transform_position, Doc, Gravity, Op, Side::*, Step,
+    Step::*,
+};
+use std::cmp::max;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Returns an op turning `old` into `new`, keeping a longest common subsequence of the two.
+///
+/// Takes time and memory proportional to the product of the lengths of the parts that differ
+/// (after trimming the common prefix and suffix).
+pub fn diff(old: &[u8], new: &[u8]) -> Op {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    // lcs[i * width + j] is the length of the longest common subsequence of `old[i..]` and
+    // `new[j..]`.
+    let width = new.len() + 1;
+    let mut lcs = vec![0; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut op = vec![Skip(prefix)];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            op.push(Skip(1));
+            i += 1;
+            j += 1;
+        } else if j < new.len()
+            && (i == old.len() || lcs[i * width + j + 1] >= lcs[(i + 1) * width + j])
+        {
+            op.push(Insert(vec![new[j]]));
+            j += 1;
+        } else {
+            op.push(Delete(1));
+            i += 1;
+        }
+    }
+    normalize(&op)
+}
+
+/// Like `diff`, but reads the documents from `old` and `new`, from their current positions to
+/// their ends. Fails if reading or seeking fails.
+///
+/// The common prefix and suffix are compared a chunk at a time and never held in memory. Only
+/// the differing middles of both documents are read into memory and diffed with `diff`, so
+/// memory use is the size of the two middles plus the product of their lengths: small for a few
+/// nearby edits in large files, but as bad as `diff` of the whole documents if they differ near
+/// both ends.
+pub fn diff_streaming(mut old: impl Read + Seek, mut new: impl Read + Seek) -> io::Result<Op> {
+    let (old_start, old_end) = (old.seek(SeekFrom::Current(0))?, old.seek(SeekFrom::End(0))?);
+    let (new_start, new_end) = (new.seek(SeekFrom::Current(0))?, new.seek(SeekFrom::End(0))?);
+    let max_common = (old_end - old_start).min(new_end - new_start);
+    let (mut a, mut b) = (vec![0; CHUNK], vec![0; CHUNK]);
+
+    let mut prefix = 0;
+    while prefix < max_common {
+        let n = (max_common - prefix).min(CHUNK as u64) as usize;
+        read_at(&mut old, old_start + prefix, &mut a[..n])?;
+        read_at(&mut new, new_start + prefix, &mut b[..n])?;
+        let common = a[..n]
+            .iter()
+            .zip(&b[..n])
+            .take_while(|(x, y)| x == y)
+            .count();
+        prefix += common as u64;
+        if common < n {
+            break;
+        }
+    }
+    let mut suffix = 0;
+    while suffix < max_common - prefix {
+        let n = (max_common - prefix - suffix).min(CHUNK as u64) as usize;
+        read_at(&mut old, old_end - suffix - n as u64, &mut a[..n])?;
+        read_at(&mut new, new_end - suffix - n as u64, &mut b[..n])?;
+        let common = a[..n]
+            .iter()
+            .rev()
+            .zip(b[..n].iter().rev())
+            .take_while(|(x, y)| x == y)
+            .count();
+        suffix += common as u64;
+        if common < n {
+            break;
+        }
+    }
+
+    let mut old_middle = vec![0; (old_end - old_start - prefix - suffix) as usize];
+    read_at(&mut old, old_start + prefix, &mut old_middle)?;
+    let mut new_middle = vec![0; (new_end - new_start - prefix - suffix) as usize];
+    read_at(&mut new, new_start + prefix, &mut new_middle)?;
+    let mut op = vec![Skip(prefix as usize)];
+    op.extend(diff(&old_middle, &new_middle).0);
+    Ok(normalize(&op))
+}
+
+/// How much `diff_streaming` compares at once.
+const CHUNK: usize = 8192;
+
+/// Fills `buf` from `reader`, starting at `pos`.
+fn read_at(mut reader: impl Read + Seek, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(buf)
+}
+
+/// Both sides of a merge changed the same part of the ancestor.
+#[derive(Eq, PartialEq, Debug)]
+pub struct MergeConflict {
+    /// The ancestor range changed by `a`.
+    pub a: Range<usize>,
+    /// The ancestor range changed by `b`, overlapping or touching `a`.
+    pub b: Range<usize>,
+}
+
+/// The ancestor ranges changed by a normalized op, one per run of steps between skips. A pure
+/// insert gives an empty range at its position.
+fn changed_ranges(op: &[Step]) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut pos = 0;
+    let mut in_change = false;
+    for step in op {
+        let start = pos;
+        match step {
+            Skip(n) => {
+                pos += n;
+                in_change = false;
+                continue;
+            }
+            Insert(_) => {}
+            Delete(n) => pos += n,
+        }
+        match ranges.last_mut() {
+            Some(range) if in_change => range.end = pos,
+            _ => ranges.push(start..pos),
+        }
+        in_change = true;
+    }
+    ranges
+}
+
+/// Whether changes to ancestor ranges `a` and `b` conflict. Changes replacing text conflict if
+/// they overlap; a pure insert conflicts with a change it touches.
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    if a.start == a.end || b.start == b.end {
+        a.start <= b.end && b.start <= a.end
+    } else {
+        a.start < b.end && b.start < a.end
+    }
+}
+
+/// Three-way merge: applies both the changes from `ancestor` to `a` and from `ancestor` to `b`
+/// to `ancestor`.
+///
+/// Fails on the first pair of changes to the same part of the ancestor, unless `a` and `b` are
+/// identical.
+pub fn merge(ancestor: &[u8], a: &[u8], b: &[u8]) -> Result<Doc, MergeConflict> {
+    if a == b {
+        return Ok(a.to_vec());
+    }
+    let op_a = diff(ancestor, a);
+    let op_b = diff(ancestor, b);
+    let ranges_b = changed_ranges(&op_b);
+    for range_a in changed_ranges(&op_a) {
+        if let Some(range_b) = ranges_b.iter().find(|range_b| overlaps(&range_a, range_b)) {
+            return Err(MergeConflict {
+                a: range_a,
+                b: range_b.clone(),
+            });
+        }
+    }
+    let mut doc = a.to_vec();
+    apply_with_scratch(&mut doc, &transform(&op_b, &op_a, Right), &mut vec![]);
+    Ok(doc)
+}
+
+/// Lines of context around the changes in `explain`.
+const CONTEXT: usize = 3;
+
+/// Splits `text` into lines, each with its `\n` except maybe the last one.
+fn split_lines(text: &[u8]) -> Vec<&[u8]> {
+    let mut lines = vec![];
+    let mut rest = text;
+    while !rest.is_empty() {
+        let len = rest
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        lines.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    lines
+}
+
+fn push_line(out: &mut String, prefix: char, line: &[u8]) {
+    out.push(prefix);
+    out.push_str(&String::from_utf8_lossy(line));
+    if line.last() != Some(&b'\n') {
+        out.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// The line numbers in a hunk header: 1-based, except for an empty range, which is given by the
+/// line before it.
+fn hunk_range(start: usize, count: usize) -> String {
+    format!("{},{}", if count == 0 { start } else { start + 1 }, count)
+}
+
+/// Renders the change `op` makes to `doc_before` as a unified diff (like `git diff`), e.g. for
+/// reviewing collaborative changes. Invalid UTF-8 is shown lossily.
+pub fn explain(op: &[Step], doc_before: &[u8]) -> String {
+    let op = normalize(op);
+    let mut doc_after = doc_before.to_vec();
+    apply_with_scratch(&mut doc_after, &op, &mut vec![]);
+
+    let lines = split_lines(doc_before);
+    // line_starts[i] is the offset of line `i`, and the last entry the length of the document.
+    let mut line_starts = vec![0];
+    for line in &lines {
+        line_starts.push(line_starts.last().unwrap() + line.len());
+    }
+    // The line of `doc_before` containing `pos`, or `lines.len()` for the end of a document
+    // ending with a line break (the end of a last line without one is on that line).
+    let ends_with_line_break = doc_before.last().map_or(true, |&byte| byte == b'\n');
+    let line_of = |pos: usize| match line_starts.binary_search(&pos) {
+        Ok(i) if i < lines.len() || ends_with_line_break => i,
+        Ok(i) | Err(i) => i - 1,
+    };
+    // Where line `i` starts in `doc_after`, behind or in front of text inserted right there.
+    let after_start = |i: usize| transform_position(line_starts[i], &op, Gravity::Before);
+    let after_end = |i: usize| transform_position(line_starts[i], &op, Gravity::After);
+    let after_lines = |start: usize, end: usize| split_lines(&doc_after[start..end]);
+
+    // Changed lines of `doc_before`, each block ending at a line boundary of `doc_after` too.
+    let mut blocks: Vec<Range<usize>> = vec![];
+    for range in changed_ranges(&op) {
+        let start = line_of(range.start);
+        let mut end = if range.start < range.end {
+            line_of(range.end - 1) + 1
+        } else if range.start == line_starts[start] {
+            start
+        } else {
+            start + 1
+        };
+        // The last line break of the block was deleted, joining it with the next line.
+        while end < lines.len() && doc_after[..after_end(end)].last() != Some(&b'\n') {
+            end += 1;
+        }
+        match blocks.last_mut() {
+            Some(last) if start <= last.end => last.end = last.end.max(end),
+            _ => blocks.push(start..end),
+        }
+    }
+
+    let mut out = String::from("--- before\n+++ after\n");
+    let mut i = 0;
+    while i < blocks.len() {
+        // Blocks with overlapping context go into the same hunk.
+        let mut j = i + 1;
+        while j < blocks.len() && blocks[j].start <= blocks[j - 1].end + 2 * CONTEXT {
+            j += 1;
+        }
+        let hunk =
+            blocks[i].start.saturating_sub(CONTEXT)..(blocks[j - 1].end + CONTEXT).min(lines.len());
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(hunk.start, hunk.len()),
+            hunk_range(
+                after_lines(0, after_start(hunk.start)).len(),
+                after_lines(after_start(hunk.start), after_end(hunk.end)).len()
+            )
+        ));
+        let mut line = hunk.start;
+        for block in &blocks[i..j] {
+            for context in &lines[line..block.start] {
+                push_line(&mut out, ' ', context);
+            }
+            for removed in &lines[block.clone()] {
+                push_line(&mut out, '-', removed);
+            }
+            for added in after_lines(after_start(block.start), after_end(block.end)) {
+                push_line(&mut out, '+', added);
+            }
+            line = block.end;
+        }
+        for context in &lines[line..hunk.end] {
+            push_line(&mut out, ' ', context);
+        }
+        i = j;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(b"abc", b"abc"), vec![]);
+        assert_eq!(
+            diff(b"abcdef", b"axcdefg"),
+            vec![
+                Skip(1),
+                Insert(b"x".to_vec()),
+                Delete(1),
+                Skip(4),
+                Insert(b"g".to_vec())
+            ]
+        );
+    }
+
+    /// Hands out at most 7 bytes per read, so that reads don't line up with anything.
+    struct Trickle<'a>(io::Cursor<&'a [u8]>);
+
+    impl<'a> Trickle<'a> {
+        fn new(bytes: &'a [u8]) -> Self {
+            Trickle(io::Cursor::new(bytes))
+        }
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(7);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    impl<'a> Seek for Trickle<'a> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_diff_streaming() {
+        let old: Doc = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new.drain(99_980..99_985);
+        new[99_990] = b'x';
+        new.extend_from_slice(b"tail");
+
+        let op = diff_streaming(Trickle::new(&old), io::Cursor::new(&new)).unwrap();
+        assert_eq!(op, diff(&old, &new));
+        let mut doc = old.clone();
+        apply_with_scratch(&mut doc, &op, &mut vec![]);
+        assert_eq!(doc, new);
+
+        assert_eq!(
+            diff_streaming(io::Cursor::new(&old), Trickle::new(&old)).unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            diff_streaming(io::Cursor::new(b"abc"), io::Cursor::new(b"ab")).unwrap(),
+            vec![Skip(2), Delete(1)]
+        );
+        // From the current position.
+        let mut old = io::Cursor::new(b"xxabc");
+        old.set_position(2);
+        assert_eq!(
+            diff_streaming(old, io::Cursor::new(b"abd")).unwrap(),
+            vec![Skip(2), Insert(b"d".to_vec()), Delete(1)]
+        );
+    }
+
+    #[test]
+    fn test_diff_streaming_edit_in_the_middle() {
+        // Too large for `diff` of the whole documents, which would need 10^12 table entries.
+        let old: Doc = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        let mut new = old.clone();
+        new.splice(500_000..500_003, b"xy".iter().copied());
+
+        let op = diff_streaming(io::Cursor::new(&old), Trickle::new(&new)).unwrap();
+        assert_eq!(op.0.first(), Some(&Skip(500_000)));
+        let mut doc = old;
+        apply_with_scratch(&mut doc, &op, &mut vec![]);
+        assert_eq!(doc, new);
+    }
+
+    #[test]
+    fn test_merge_disjoint_edits() {
+        assert_eq!(
+            merge(b"one two three", b"one 2 three", b"one two three four"),
+            Ok(b"one 2 three four".to_vec())
+        );
+        assert_eq!(merge(b"abc", b"xabc", b"xabc"), Ok(b"xabc".to_vec()));
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        assert_eq!(
+            merge(b"one two three", b"one 2 three", b"one too three"),
+            Err(MergeConflict { a: 4..7, b: 5..6 })
+        );
+        // Both insert at the same position.
+        assert_eq!(
+            merge(b"ab", b"axb", b"ayb"),
+            Err(MergeConflict { a: 1..1, b: 1..1 })
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let before = b"one\ntwo\nthree\nfour\nfive\n";
+        assert_eq!(
+            explain(&diff(before, b"one\ntwo\n3\nfour\nfive\n"), before),
+            "--- before\n+++ after\n@@ -1,5 +1,5 @@\n one\n two\n-three\n+3\n four\n five\n"
+        );
+        // Joining two lines.
+        assert_eq!(
+            explain(&[Skip(1), Delete(1)], b"a\nb\n"),
+            "--- before\n+++ after\n@@ -1,2 +1,1 @@\n-a\n-b\n+ab\n"
+        );
+        assert_eq!(
+            explain(&[Skip(2), Insert(b"c".to_vec())], b"ab"),
+            "--- before\n+++ after\n@@ -1,1 +1,1 @@\n-ab\n\\ No newline at end of file\n\
+             +abc\n\\ No newline at end of file\n"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn diff_turns_old_into_new(old in any::<Doc>(), new in any::<Doc>()) {
+            let mut doc = old.clone();
+            apply_with_scratch(&mut doc, &diff(&old, &new), &mut vec![]);
+            prop_assert_eq!(doc, new);
+        }
+    }
+}
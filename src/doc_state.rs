@@ -0,0 +1,71 @@
+use crate::composite_op::{input_len, ApplyError, Step, Step::*};
+
+/// The length and version of a document, without its contents. Enough to validate a stream of
+/// ops, e.g. on a relay that never materializes the documents it forwards.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct DocState {
+    pub len: usize,
+    pub version: usize,
+}
+
+impl DocState {
+    pub fn new(len: usize, version: usize) -> Self {
+        DocState { len, version }
+    }
+
+    /// Checks that `op` can be applied to a document of length `len`.
+    pub fn check(&self, op: &[Step]) -> Result<(), ApplyError> {
+        let input_len = input_len(op);
+        if input_len > self.len {
+            return Err(ApplyError::OutOfBounds {
+                input_len,
+                doc_len: self.len,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks `op` and advances the state past it.
+    pub fn apply(&mut self, op: &[Step]) -> Result<(), ApplyError> {
+        self.check(op)?;
+        for step in op {
+            match step {
+                Skip(_) => {}
+                Insert(s) => self.len += s.len(),
+                Delete(n) => self.len -= n,
+            }
+        }
+        self.version += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let state = DocState::new(3, 7);
+        assert_eq!(state.check(&[Skip(1), Delete(2)]), Ok(()));
+        assert_eq!(state.check(&[Skip(3), Insert(b"x".to_vec())]), Ok(()));
+        assert_eq!(
+            state.check(&[Skip(2), Delete(2)]),
+            Err(ApplyError::OutOfBounds {
+                input_len: 4,
+                doc_len: 3
+            })
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut state = DocState::new(3, 7);
+        state
+            .apply(&[Skip(1), Insert(b"xy".to_vec()), Delete(1)])
+            .unwrap();
+        assert_eq!(state, DocState::new(4, 8));
+        assert!(state.apply(&[Delete(5)]).is_err());
+        assert_eq!(state, DocState::new(4, 8));
+    }
+}
@@ -0,0 +1,81 @@
+//! Documents and ops tagged with the session they belong to, so that an op from one session
+//! can't silently be applied to another session's document.
+
+use crate::composite_op::{apply_with_scratch, Doc, Op};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct SessionId(pub u64);
+
+/// An op of a given session.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SessionOp {
+    pub session: SessionId,
+    pub op: Op,
+}
+
+/// A document of a given session.
+#[derive(Debug, Clone)]
+pub struct Document {
+    session: SessionId,
+    bytes: Doc,
+    scratch: Doc,
+}
+
+impl Document {
+    pub fn new(session: SessionId, bytes: Doc) -> Self {
+        Document {
+            session,
+            bytes,
+            scratch: vec![],
+        }
+    }
+
+    pub fn session(&self) -> SessionId {
+        self.session
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Doc {
+        self.bytes
+    }
+
+    /// Tags `op` with this document's session.
+    pub fn tag(&self, op: impl Into<Op>) -> SessionOp {
+        SessionOp {
+            session: self.session,
+            op: op.into(),
+        }
+    }
+
+    /// Applies `op`. In debug builds, panics if it belongs to another session.
+    pub fn apply(&mut self, op: &SessionOp) {
+        debug_assert_eq!(op.session, self.session, "op from another session");
+        apply_with_scratch(&mut self.bytes, &op.op, &mut self.scratch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+
+    #[test]
+    fn test_apply_same_session() {
+        let mut doc = Document::new(SessionId(1), b"abc".to_vec());
+        let op = doc.tag(vec![Skip(1), Insert(b"x".to_vec())]);
+        doc.apply(&op);
+        assert_eq!(doc.bytes(), b"axbc");
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "op from another session")]
+    fn test_apply_other_session() {
+        let mut doc = Document::new(SessionId(1), b"abc".to_vec());
+        let other = Document::new(SessionId(2), b"abc".to_vec());
+        doc.apply(&other.tag(vec![Delete(1)]));
+    }
+}
@@ -0,0 +1,108 @@
+//! Flat retain/insert/delete edit scripts, for exchanging ops with other OT libraries.
+//!
+//! With the `serde` feature, a script serializes to the usual JSON array of `{"retain": n}`,
+//! `{"insert": "text"}` and `{"delete": n}` objects. Counts are in bytes.
+
+use crate::composite_op::{Op, Step};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::string::FromUtf8Error;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ScriptOp {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct EditScript(pub Vec<ScriptOp>);
+
+impl From<EditScript> for Op {
+    fn from(script: EditScript) -> Op {
+        script
+            .0
+            .into_iter()
+            .map(|op| match op {
+                ScriptOp::Retain(n) => Step::Skip(n),
+                ScriptOp::Insert(s) => Step::Insert(s.into_bytes()),
+                ScriptOp::Delete(n) => Step::Delete(n),
+            })
+            .collect()
+    }
+}
+
+/// Fails if an inserted chunk isn't valid UTF-8, since scripts carry inserts as strings.
+impl TryFrom<Op> for EditScript {
+    type Error = FromUtf8Error;
+
+    fn try_from(op: Op) -> Result<EditScript, FromUtf8Error> {
+        op.into_iter()
+            .map(|step| {
+                Ok(match step {
+                    Step::Skip(n) => ScriptOp::Retain(n),
+                    Step::Insert(bytes) => ScriptOp::Insert(String::from_utf8(bytes)?),
+                    Step::Delete(n) => ScriptOp::Delete(n),
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(EditScript)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_round_trip() {
+        let op = Op(vec![Skip(2), Insert("zażółć".into()), Delete(3), Skip(1)]);
+        let script: EditScript = op.clone().try_into().unwrap();
+        assert_eq!(
+            script,
+            EditScript(vec![
+                ScriptOp::Retain(2),
+                ScriptOp::Insert("zażółć".to_string()),
+                ScriptOp::Delete(3),
+                ScriptOp::Retain(1),
+            ])
+        );
+        assert_eq!(Op::from(script), op);
+    }
+
+    #[test]
+    fn test_non_utf8_insert() {
+        assert!(EditScript::try_from(Op(vec![Insert(vec![0xff])])).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_decode_json() {
+        use crate::composite_op::apply_with_scratch;
+
+        let script: EditScript =
+            serde_json::from_str(r#"[{"retain": 1}, {"insert": "xy"}, {"delete": 1}]"#).unwrap();
+        let mut doc = b"abc".to_vec();
+        apply_with_scratch(&mut doc, &script.into(), &mut vec![]);
+        assert_eq!(doc, b"axyc");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_encode_json() {
+        let script = EditScript(vec![ScriptOp::Retain(1), ScriptOp::Insert("x".to_string())]);
+        assert_eq!(
+            serde_json::to_string(&script).unwrap(),
+            r#"[{"retain":1},{"insert":"x"}]"#
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! The messages clients and the server exchange: ops tagged with an id that survives transforms.
+
+use crate::composite_op::Op;
+
+/// Identifies an op sent by a client across transforms, so that the client can recognize it when
+/// the server broadcasts it back.
+#[derive(Eq, PartialEq, Hash, Debug, Clone, Copy)]
+pub struct OpId {
+    pub client: u64,
+    /// Counts the ops sent by the client.
+    pub seq: u64,
+}
+
+/// An op as sent between clients and the server. The server transforms `op` but keeps `id`.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Envelope {
+    pub id: OpId,
+    pub op: Op,
+}
@@ -0,0 +1,100 @@
+//! Turning editor input events into ops.
+
+use crate::composite_op::{splice, Op};
+
+/// An editing event at the cursor.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Event {
+    InsertChar(char),
+    /// Deletes the character before the cursor.
+    Backspace,
+    /// Deletes the character after the cursor.
+    Delete,
+    Paste(String),
+}
+
+fn is_continuation_byte(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// The op for `event` with the cursor at byte offset `cursor` in `doc`. Backspace and Delete
+/// remove a whole UTF-8 encoded character, and give an identity op at the start and the end of
+/// the document respectively.
+pub fn event_to_op(event: &Event, doc: &[u8], cursor: usize) -> Op {
+    let cursor = cursor.min(doc.len());
+    match event {
+        Event::InsertChar(c) => splice(cursor, 0, c.encode_utf8(&mut [0; 4]).as_bytes()),
+        Event::Paste(text) => splice(cursor, 0, text),
+        Event::Backspace => {
+            let start = doc[..cursor]
+                .iter()
+                .rposition(|&byte| !is_continuation_byte(byte))
+                .unwrap_or(0);
+            splice(start, cursor - start, b"")
+        }
+        Event::Delete => {
+            let len = doc[cursor..]
+                .iter()
+                .skip(1)
+                .position(|&byte| !is_continuation_byte(byte))
+                .map_or(doc.len() - cursor, |n| n + 1);
+            splice(cursor, len, b"")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::{apply, Step::*};
+
+    fn applied(doc: &[u8], op: &Op) -> Vec<u8> {
+        let mut doc = doc.to_vec();
+        apply(&mut doc, op);
+        doc
+    }
+
+    #[test]
+    fn test_insert_char() {
+        let op = event_to_op(&Event::InsertChar('x'), b"ab", 1);
+        assert_eq!(op, vec![Skip(1), Insert(b"x".to_vec())]);
+        let op = event_to_op(&Event::InsertChar('é'), b"ab", 2);
+        assert_eq!(applied(b"ab", &op), "abé".as_bytes());
+    }
+
+    #[test]
+    fn test_paste() {
+        let op = event_to_op(&Event::Paste("xyz".to_string()), b"ab", 0);
+        assert_eq!(op, vec![Insert(b"xyz".to_vec())]);
+        assert_eq!(event_to_op(&Event::Paste(String::new()), b"ab", 0), vec![]);
+    }
+
+    #[test]
+    fn test_backspace() {
+        assert_eq!(
+            event_to_op(&Event::Backspace, b"abc", 2),
+            vec![Skip(1), Delete(1)]
+        );
+        assert_eq!(event_to_op(&Event::Backspace, b"abc", 0), Op::identity());
+        let doc = "aé".as_bytes();
+        assert_eq!(
+            event_to_op(&Event::Backspace, doc, 3),
+            vec![Skip(1), Delete(2)]
+        );
+    }
+
+    #[test]
+    fn test_delete() {
+        assert_eq!(
+            event_to_op(&Event::Delete, b"abc", 1),
+            vec![Skip(1), Delete(1)]
+        );
+        assert_eq!(event_to_op(&Event::Delete, b"abc", 3), Op::identity());
+        let doc = "éa".as_bytes();
+        assert_eq!(event_to_op(&Event::Delete, doc, 0), vec![Delete(2)]);
+        assert_eq!(
+            event_to_op(&Event::Delete, "aé".as_bytes(), 1),
+            vec![Skip(1), Delete(2)]
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use crate::composite_op::{self, insert_tiebreak};
+use std::cmp::Ordering::Less;
+
+/// A document as a list of lines, without line breaks. The empty document is a single empty
+/// line.
+pub type Doc = Vec<Vec<u8>>;
+
+/// An edit of a `Doc`, at a `(row, column)` position.
+///
+/// Seen as a flat text with a line break at the end of each line but the last, `SplitLine`
+/// inserts a line break and `JoinLine` deletes one, which is how they transform against the
+/// character ops.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Op {
+    InsertChar(usize, usize, u8),
+    DeleteChar(usize, usize),
+    /// Moves the text of the line from the column on to a new line below it.
+    SplitLine(usize, usize),
+    /// Appends the next line to the line. The column is the length of the line, where the
+    /// appended text starts: `transform` needs it to move positions on the next line, and doesn't
+    /// see the document. See `join_line`.
+    JoinLine(usize, usize),
+    Noop,
+}
+use Op::*;
+
+impl Op {
+    /// The op that changes nothing.
+    pub const fn identity() -> Op {
+        Noop
+    }
+}
+
+pub fn apply(doc: &mut Doc, op: &Op) {
+    match *op {
+        InsertChar(row, col, c) => doc[row].insert(col, c),
+        DeleteChar(row, col) => {
+            doc[row].remove(col);
+        }
+        SplitLine(row, col) => {
+            let tail = doc[row].split_off(col);
+            doc.insert(row + 1, tail);
+        }
+        JoinLine(row, _) => {
+            let next = doc.remove(row + 1);
+            doc[row].extend(next);
+        }
+        Noop => {}
+    }
+}
+
+/// The op joining line `row` of `doc` with the next one, if there is one.
+pub fn join_line(doc: &[Vec<u8>], row: usize) -> Option<Op> {
+    if row + 1 < doc.len() {
+        Some(JoinLine(row, doc[row].len()))
+    } else {
+        None
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+use Side::*;
+
+impl From<Side> for composite_op::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Left => composite_op::Side::Left,
+            Right => composite_op::Side::Right,
+        }
+    }
+}
+
+fn position(op: &Op) -> Option<(usize, usize)> {
+    match *op {
+        InsertChar(row, col, _)
+        | DeleteChar(row, col)
+        | SplitLine(row, col)
+        | JoinLine(row, col) => Some((row, col)),
+        Noop => None,
+    }
+}
+
+fn with_position(op: &Op, (row, col): (usize, usize)) -> Op {
+    match *op {
+        InsertChar(_, _, c) => InsertChar(row, col, c),
+        DeleteChar(_, _) => DeleteChar(row, col),
+        SplitLine(_, _) => SplitLine(row, col),
+        JoinLine(_, _) => JoinLine(row, col),
+        Noop => Noop,
+    }
+}
+
+fn is_insert(op: &Op) -> bool {
+    match op {
+        InsertChar(_, _, _) | SplitLine(_, _) => true,
+        _ => false,
+    }
+}
+
+/// Where the position `(row, col)` ends up once `op` is applied. `before` decides whether it
+/// stays in front of a character or line break inserted exactly at it.
+fn transform_position((row, col): (usize, usize), op: &Op, before: bool) -> (usize, usize) {
+    match *op {
+        InsertChar(r, c, _) if row == r && (col > c || col == c && !before) => (row, col + 1),
+        SplitLine(r, c) if row == r && (col > c || col == c && !before) => (row + 1, col - c),
+        SplitLine(r, _) if row > r => (row + 1, col),
+        DeleteChar(r, c) if row == r && col > c => (row, col - 1),
+        JoinLine(r, len) if row == r + 1 => (r, col + len),
+        JoinLine(r, _) if row > r + 1 => (row - 1, col),
+        _ => (row, col),
+    }
+}
+
+/// Takes two operations defined on the same initial document,
+/// and returns an operation equivalent to `op1` which can be applied after `op2`.
+///
+/// When both ops insert (a character or a line break) at the same position, `op1`'s goes first
+/// if `side` is `Left`.
+///
+/// Satisfies TP1, including for a line split concurrently with joining it to the next one.
+pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
+    let (pos1, pos2) = match (position(op1), position(op2)) {
+        (Some(pos1), Some(pos2)) => (pos1, pos2),
+        _ => return op1.clone(),
+    };
+    if is_insert(op1) {
+        let before = insert_tiebreak(pos1, pos2, side.into()) == Less;
+        with_position(op1, transform_position(pos1, op2, before))
+    } else if !is_insert(op2) && pos1 == pos2 {
+        // Both ops deleted the same character or line break.
+        Noop
+    } else {
+        with_position(op1, transform_position(pos1, op2, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(lines: &[&[u8]]) -> Doc {
+        lines.iter().map(|line| line.to_vec()).collect()
+    }
+
+    fn applied(doc: &[Vec<u8>], op: &Op) -> Doc {
+        let mut doc = doc.to_vec();
+        apply(&mut doc, op);
+        doc
+    }
+
+    fn converged(doc: &[Vec<u8>], op1: &Op, op2: &Op) -> Doc {
+        let doc1 = applied(&applied(doc, op1), &transform(op2, op1, Right));
+        let doc2 = applied(&applied(doc, op2), &transform(op1, op2, Left));
+        assert_eq!(doc1, doc2, "op1 = {:?}, op2 = {:?}", op1, op2);
+        doc1
+    }
+
+    /// Every op valid on `doc`, inserting `c`.
+    fn valid_ops(doc: &[Vec<u8>], c: u8) -> Vec<Op> {
+        let mut ops = vec![Noop];
+        for (row, line) in doc.iter().enumerate() {
+            for col in 0..=line.len() {
+                ops.push(InsertChar(row, col, c));
+                ops.push(SplitLine(row, col));
+            }
+            for col in 0..line.len() {
+                ops.push(DeleteChar(row, col));
+            }
+        }
+        ops.extend((0..doc.len()).filter_map(|row| join_line(doc, row)));
+        ops
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut d = doc(&[b"abc", b"de"]);
+        apply(&mut d, &InsertChar(1, 2, b'f'));
+        assert_eq!(d, doc(&[b"abc", b"def"]));
+        apply(&mut d, &DeleteChar(0, 0));
+        assert_eq!(d, doc(&[b"bc", b"def"]));
+        apply(&mut d, &SplitLine(1, 1));
+        assert_eq!(d, doc(&[b"bc", b"d", b"ef"]));
+        let join = join_line(&d, 0).unwrap();
+        assert_eq!(join, JoinLine(0, 2));
+        apply(&mut d, &join);
+        assert_eq!(d, doc(&[b"bcd", b"ef"]));
+        assert_eq!(join_line(&d, 1), None);
+    }
+
+    #[test]
+    fn test_identity() {
+        let op = SplitLine(0, 1);
+        assert_eq!(transform(&op, &Op::identity(), Left), op);
+        assert_eq!(transform(&Op::identity(), &op, Left), Op::identity());
+        assert_eq!(applied(&doc(&[b"ab"]), &Op::identity()), doc(&[b"ab"]));
+    }
+
+    #[test]
+    fn test_char_ops_same_line() {
+        let d = doc(&[b"abcd"]);
+        assert_eq!(
+            converged(&d, &InsertChar(0, 1, b'x'), &InsertChar(0, 3, b'y')),
+            doc(&[b"axbcyd"])
+        );
+        assert_eq!(
+            converged(&d, &InsertChar(0, 2, b'x'), &InsertChar(0, 2, b'y')),
+            doc(&[b"abxycd"])
+        );
+        assert_eq!(
+            converged(&d, &InsertChar(0, 3, b'x'), &DeleteChar(0, 1)),
+            doc(&[b"acxd"])
+        );
+        assert_eq!(
+            converged(&d, &DeleteChar(0, 2), &DeleteChar(0, 2)),
+            doc(&[b"abd"])
+        );
+    }
+
+    #[test]
+    fn test_split_vs_join_same_line() {
+        let d = doc(&[b"abc", b"de"]);
+        let join = JoinLine(0, 3);
+        assert_eq!(
+            converged(&d, &SplitLine(0, 1), &join),
+            doc(&[b"a", b"bcde"])
+        );
+        assert_eq!(transform(&join, &SplitLine(0, 1), Right), JoinLine(1, 2));
+        // Splitting right where the lines are joined.
+        assert_eq!(
+            converged(&d, &SplitLine(0, 3), &join),
+            doc(&[b"abc", b"de"])
+        );
+        assert_eq!(converged(&d, &join, &join), doc(&[b"abcde"]));
+    }
+
+    #[test]
+    fn test_line_ops_move_chars() {
+        let d = doc(&[b"abc", b"de"]);
+        assert_eq!(
+            transform(&DeleteChar(1, 1), &JoinLine(0, 3), Left),
+            DeleteChar(0, 4)
+        );
+        assert_eq!(
+            transform(&InsertChar(0, 2, b'x'), &SplitLine(0, 1), Left),
+            InsertChar(1, 1, b'x')
+        );
+        assert_eq!(
+            converged(&d, &InsertChar(1, 0, b'x'), &JoinLine(0, 3)),
+            doc(&[b"abcxde"])
+        );
+    }
+
+    #[test]
+    fn test_tp1_exhaustive() {
+        for d in &[doc(&[b"ab", b"c", b""]), doc(&[b""])] {
+            for op1 in valid_ops(d, b'x') {
+                for op2 in valid_ops(d, b'y') {
+                    converged(d, &op1, &op2);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::composite_op::{apply_with_scratch, is_identity, len_delta, Doc, Op, Step};
+use std::collections::BTreeMap;
+
+/// A deterministic, non-cryptographic hash of a document (64-bit FNV-1a). Equal documents hash
+/// equally across platforms and runs.
+pub fn doc_hash(doc: &[u8]) -> u64 {
+    doc.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Applies all ops in `log`, in order, to `initial`.
+pub fn replay(initial: Doc, log: &[Op]) -> Doc {
+    let mut doc = initial;
+    let mut scratch = vec![];
+    for op in log {
+        apply_with_scratch(&mut doc, op, &mut scratch);
+    }
+    doc
+}
+
+/// The document after each op of `log`, in order. See `ReplayIter` for a lazy version.
+pub fn replay_states(initial: Doc, log: &[Op]) -> Vec<Doc> {
+    ReplayIter::new(initial, log).collect()
+}
+
+/// An iterator applying the ops of a log one by one, yielding the document after each of them.
+/// Only the current state is kept, so long histories can be stepped through.
+#[derive(Debug, Clone)]
+pub struct ReplayIter<'a> {
+    doc: Doc,
+    scratch: Doc,
+    ops: std::slice::Iter<'a, Op>,
+}
+
+impl<'a> ReplayIter<'a> {
+    pub fn new(initial: Doc, log: &'a [Op]) -> Self {
+        ReplayIter {
+            doc: initial,
+            scratch: vec![],
+            ops: log.iter(),
+        }
+    }
+
+    /// The document after the ops yielded so far.
+    pub fn document(&self) -> &[u8] {
+        &self.doc
+    }
+}
+
+impl<'a> Iterator for ReplayIter<'a> {
+    type Item = Doc;
+
+    fn next(&mut self) -> Option<Doc> {
+        let op = self.ops.next()?;
+        apply_with_scratch(&mut self.doc, op, &mut self.scratch);
+        Some(self.doc.clone())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.ops.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for ReplayIter<'a> {}
+
+/// An entry of a persisted op log.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct LogEntry {
+    pub op: Op,
+    /// `doc_hash` of the document after applying `op`, if recorded.
+    pub expected_hash: Option<u64>,
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct HashMismatch {
+    /// The version (number of ops applied) at which the document didn't match.
+    pub version: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+/// Like `replay`, but checks the document against each entry's `expected_hash` along the way,
+/// failing on the first mismatch.
+pub fn replay_verified(initial: Doc, log: &[LogEntry]) -> Result<Doc, HashMismatch> {
+    let mut doc = initial;
+    let mut scratch = vec![];
+    for (i, entry) in log.iter().enumerate() {
+        apply_with_scratch(&mut doc, &entry.op, &mut scratch);
+        if let Some(expected) = entry.expected_hash {
+            let actual = doc_hash(&doc);
+            if actual != expected {
+                return Err(HashMismatch {
+                    version: i + 1,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(doc)
+}
+
+/// An op of a history together with who made it.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct AuthoredOp {
+    pub author: u64,
+    pub op: Op,
+}
+
+/// A summary of an op history.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct OpStats {
+    /// Number of inserted bytes.
+    pub inserted: usize,
+    /// Number of deleted bytes.
+    pub deleted: usize,
+    /// The change in document length over the whole history.
+    pub net_growth: isize,
+    /// Number of ops that don't change the document.
+    pub noops: usize,
+    /// Number of ops made by each author, no-ops included.
+    pub ops_per_author: BTreeMap<u64, usize>,
+}
+
+/// Summarizes `history`, e.g. for a dashboard.
+pub fn stats(history: &[AuthoredOp]) -> OpStats {
+    let mut stats = OpStats::default();
+    for AuthoredOp { author, op } in history {
+        for step in op {
+            match step {
+                Step::Insert(s) => stats.inserted += s.len(),
+                Step::Delete(n) => stats.deleted += n,
+                Step::Skip(_) => {}
+            }
+        }
+        stats.net_growth += len_delta(op);
+        if is_identity(op) {
+            stats.noops += 1;
+        }
+        *stats.ops_per_author.entry(*author).or_insert(0) += 1;
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+
+    #[test]
+    fn test_doc_hash() {
+        assert_eq!(doc_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(doc_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_ne!(doc_hash(b"ab"), doc_hash(b"ba"));
+    }
+
+    fn log() -> Vec<LogEntry> {
+        vec![
+            LogEntry {
+                op: Op(vec![Insert(b"abc".to_vec())]),
+                expected_hash: Some(doc_hash(b"abc")),
+            },
+            LogEntry {
+                op: Op(vec![Skip(1), Delete(1)]),
+                expected_hash: None,
+            },
+            LogEntry {
+                op: Op(vec![Skip(2), Insert(b"d".to_vec())]),
+                expected_hash: Some(doc_hash(b"acd")),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_replay_verified() {
+        assert_eq!(replay_verified(vec![], &log()), Ok(b"acd".to_vec()));
+        let ops: Vec<Op> = log().into_iter().map(|entry| entry.op).collect();
+        assert_eq!(replay(vec![], &ops), b"acd");
+    }
+
+    #[test]
+    fn test_replay_iter() {
+        let ops: Vec<Op> = log().into_iter().map(|entry| entry.op).collect();
+        let mut states = ReplayIter::new(vec![], &ops);
+        assert_eq!(states.len(), 3);
+        assert_eq!(states.next(), Some(b"abc".to_vec()));
+        assert_eq!(states.document(), b"abc");
+        assert_eq!(states.next(), Some(b"ac".to_vec()));
+        assert_eq!(states.next(), Some(b"acd".to_vec()));
+        assert_eq!(states.next(), None);
+        assert_eq!(states.document(), b"acd");
+
+        assert_eq!(
+            replay_states(vec![], &ops),
+            vec![b"abc".to_vec(), b"ac".to_vec(), b"acd".to_vec()]
+        );
+        assert_eq!(replay_states(b"abc".to_vec(), &[]), Vec::<Doc>::new());
+    }
+
+    #[test]
+    fn test_replay_verified_rejects_tampered_log() {
+        let mut log = log();
+        log[1].op = Op(vec![Delete(1)]);
+        assert_eq!(
+            replay_verified(vec![], &log),
+            Err(HashMismatch {
+                version: 3,
+                expected: doc_hash(b"acd"),
+                actual: doc_hash(b"bcd"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let authored = |author, op: Vec<Step>| AuthoredOp { author, op: Op(op) };
+        let history = vec![
+            authored(1, vec![Insert(b"hello".to_vec())]),
+            authored(2, vec![Skip(5), Insert(b" world".to_vec())]),
+            authored(1, vec![Skip(3)]),
+            authored(2, vec![Delete(2), Insert(b"J".to_vec())]),
+            authored(3, vec![]),
+            authored(1, vec![Skip(1), Delete(3)]),
+        ];
+        let mut ops_per_author = BTreeMap::new();
+        ops_per_author.insert(1, 3);
+        ops_per_author.insert(2, 2);
+        ops_per_author.insert(3, 1);
+        assert_eq!(
+            stats(&history),
+            OpStats {
+                inserted: 12,
+                deleted: 5,
+                net_growth: 7,
+                noops: 2,
+                ops_per_author,
+            }
+        );
+        assert_eq!(stats(&[]), OpStats::default());
+    }
+}
@@ -0,0 +1,237 @@
+//! Composite ops whose inserted chunks are shared, for histories where the same short strings
+//! (emoji shortcodes, mentions, ...) get inserted over and over.
+
+use crate::composite_op::{
+    is_identity, transform_into, Op, Shape, Side, Step, TransformResult, TransformStep,
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum InternedStep {
+    Skip(usize),
+    Insert(Arc<[u8]>),
+    Delete(usize),
+}
+
+/// A composite op with interned inserts, see `OpInterner`.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct InternedOp(pub Vec<InternedStep>);
+
+impl InternedOp {
+    /// Copies the op out into a plain `Op`.
+    pub fn to_op(&self) -> Op {
+        self.0
+            .iter()
+            .map(|step| match step {
+                InternedStep::Skip(n) => Step::Skip(*n),
+                InternedStep::Insert(s) => Step::Insert(s.to_vec()),
+                InternedStep::Delete(n) => Step::Delete(*n),
+            })
+            .collect()
+    }
+}
+
+/// Deduplicates inserted chunks: equal chunks interned by the same interner share one
+/// allocation.
+#[derive(Debug, Clone, Default)]
+pub struct OpInterner {
+    chunks: HashSet<Arc<[u8]>>,
+}
+
+impl OpInterner {
+    pub fn new() -> Self {
+        OpInterner::default()
+    }
+
+    /// The shared copy of `chunk`.
+    pub fn intern_chunk(&mut self, chunk: &[u8]) -> Arc<[u8]> {
+        if let Some(interned) = self.chunks.get(chunk) {
+            return interned.clone();
+        }
+        let interned: Arc<[u8]> = Arc::from(chunk);
+        self.chunks.insert(interned.clone());
+        interned
+    }
+
+    pub fn intern(&mut self, op: &[Step]) -> InternedOp {
+        InternedOp(
+            op.iter()
+                .map(|step| match step {
+                    Step::Skip(n) => InternedStep::Skip(*n),
+                    Step::Insert(s) => InternedStep::Insert(self.intern_chunk(s)),
+                    Step::Delete(n) => InternedStep::Delete(*n),
+                })
+                .collect(),
+        )
+    }
+
+    /// The number of distinct chunks interned.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl TransformStep for InternedStep {
+    fn shape(&self) -> Shape {
+        match self {
+            InternedStep::Skip(n) => Shape::Skip(*n),
+            InternedStep::Insert(s) => Shape::Insert(s.len()),
+            InternedStep::Delete(n) => Shape::Delete(*n),
+        }
+    }
+
+    fn chunk(&self) -> &[u8] {
+        match self {
+            InternedStep::Insert(s) => s,
+            _ => &[],
+        }
+    }
+}
+
+/// Merges skips and deletes into the preceding step of the same kind, but not inserts, which
+/// would need a new chunk.
+impl<'a> TransformResult<'a, InternedStep> for Vec<InternedStep> {
+    fn push_skip(&mut self, n: usize) {
+        match self.last_mut() {
+            Some(InternedStep::Skip(m)) => *m = m.saturating_add(n),
+            _ => self.push(InternedStep::Skip(n)),
+        }
+    }
+
+    fn push_delete(&mut self, n: usize) {
+        match self.last_mut() {
+            Some(InternedStep::Delete(m)) => *m = m.saturating_add(n),
+            _ => self.push(InternedStep::Delete(n)),
+        }
+    }
+
+    fn push_insert(&mut self, insert: &'a InternedStep) {
+        self.push(insert.clone());
+    }
+
+    fn pop_trailing_skip(&mut self) {
+        if let Some(InternedStep::Skip(_)) = self.last() {
+            self.pop();
+        }
+    }
+}
+
+/// `composite_op::transform` for an interned `op1`. The result keeps `op1`'s chunks, so it
+/// stays interned. Unlike `composite_op::transform`, adjacent inserts aren't merged (which would
+/// need a new chunk), so the result is only equivalent to the normalized one.
+pub fn transform(op1: &InternedOp, op2: &[Step], side: Side) -> InternedOp {
+    if is_identity(op2) {
+        return op1.clone();
+    }
+    let mut result = vec![];
+    transform_into(&op1.0, op2, side, &mut result);
+    InternedOp(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::{self, normalize, Side::*, Step::*};
+    use proptest::prelude::*;
+
+    fn arb_op() -> impl Strategy<Value = Vec<Step>> {
+        proptest::collection::vec(
+            prop_oneof![
+                (0..4usize).prop_map(Skip),
+                proptest::collection::vec(any::<u8>(), 0..3).prop_map(Insert),
+                (0..4usize).prop_map(Delete),
+            ],
+            0..6,
+        )
+    }
+
+    #[test]
+    fn test_equal_inserts_share_storage() {
+        let mut interner = OpInterner::new();
+        let op1 = interner.intern(&[Skip(3), Insert(b":+1:".to_vec())]);
+        let op2 = interner.intern(&[Insert(b":+1:".to_vec()), Skip(1), Insert(b"@ann".to_vec())]);
+        assert_eq!(interner.len(), 2);
+        match (&op1.0[1], &op2.0[0]) {
+            (InternedStep::Insert(s1), InternedStep::Insert(s2)) => assert!(Arc::ptr_eq(s1, s2)),
+            steps => panic!("not inserts: {:?}", steps),
+        }
+        assert_eq!(
+            op2.to_op(),
+            vec![Insert(b":+1:".to_vec()), Skip(1), Insert(b"@ann".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_transform_keeps_interned_chunks() {
+        let mut interner = OpInterner::new();
+        let op1 = interner.intern(&[Skip(3), Insert(b":+1:".to_vec()), Skip(1), Delete(2)]);
+        let op2 = vec![Insert(b"ab".to_vec()), Skip(4), Delete(1)];
+        let transformed = transform(&op1, &op2, Left);
+        assert_eq!(
+            transformed.to_op(),
+            vec![Skip(5), Insert(b":+1:".to_vec()), Skip(1), Delete(1)]
+        );
+        match (&op1.0[1], &transformed.0[1]) {
+            (InternedStep::Insert(s1), InternedStep::Insert(s2)) => assert!(Arc::ptr_eq(s1, s2)),
+            steps => panic!("not inserts: {:?}", steps),
+        }
+    }
+
+    #[test]
+    fn test_transform_matches_composite_transform() {
+        let ops = vec![
+            vec![
+                Skip(1),
+                Insert(b"x".to_vec()),
+                Delete(2),
+                Insert(b"y".to_vec()),
+            ],
+            vec![Delete(1), Insert(b"ab".to_vec()), Skip(1), Delete(1)],
+            vec![
+                Skip(1),
+                Insert(b"z".to_vec()),
+                Skip(2),
+                Insert(b"w".to_vec()),
+            ],
+            vec![Skip(2), Delete(2)],
+            vec![],
+        ];
+        let mut interner = OpInterner::new();
+        for op1 in &ops {
+            let interned = interner.intern(op1);
+            for op2 in &ops {
+                for &side in &[Left, Right] {
+                    assert_eq!(
+                        normalize(&transform(&interned, op2, side).to_op()),
+                        normalize(&composite_op::transform(op1, op2, side)),
+                        "{:?} against {:?}, {:?}",
+                        op1,
+                        op2,
+                        side
+                    );
+                }
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn transform_matches_composite_transform_on_arbitrary_ops(
+            op1 in arb_op(),
+            op2 in arb_op(),
+            left in any::<bool>(),
+        ) {
+            let side = if left { Left } else { Right };
+            let interned = OpInterner::new().intern(&op1);
+            prop_assert_eq!(
+                normalize(&transform(&interned, &op2, side).to_op()),
+                normalize(&composite_op::transform(&op1, &op2, side))
+            );
+        }
+    }
+}
@@ -1,2 +1,24 @@
+pub mod checksum;
+pub mod client;
+pub mod codec;
 pub mod composite_op;
+pub mod diff;
+pub mod doc_state;
+pub mod document;
+pub mod edit_script;
+pub mod envelope;
+pub mod events;
+pub mod grid;
+pub mod history;
+pub mod intern;
+pub mod line_col;
+pub mod prelude;
+pub mod rich_text;
+pub mod server;
+#[cfg(feature = "ed25519-dalek")]
+pub mod signing;
 pub mod single_op;
+pub mod text;
+
+#[cfg(test)]
+mod test_support;
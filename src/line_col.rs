@@ -0,0 +1,275 @@
+use crate::composite_op::{affected_range, normalize, splice, Op, Step};
+use std::ops::Range;
+
+/// Which byte sequences end a line.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum LineEnding {
+    /// Only `\n` ends a line.
+    Lf,
+    /// Only `\r\n` ends a line; a lone `\r` or `\n` is an ordinary byte.
+    CrLf,
+    /// `\r\n`, `\n` and a bare `\r` all end a line, decided per occurrence.
+    Auto,
+}
+use LineEnding::*;
+
+/// Iterates over line breaks in `doc`, yielding `(offset, length)` of each break.
+fn line_breaks(doc: &[u8], ending: LineEnding) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        while i < doc.len() {
+            let start = i;
+            let crlf = doc[i] == b'\r' && doc.get(i + 1) == Some(&b'\n');
+            let len = match (doc[i], ending) {
+                (b'\n', Lf) | (b'\n', Auto) => 1,
+                (b'\r', CrLf) | (b'\r', Auto) if crlf => 2,
+                (b'\r', Auto) => 1,
+                _ => 0,
+            };
+            if len > 0 {
+                i += len;
+                return Some((start, len));
+            }
+            i += 1;
+        }
+        None
+    })
+}
+
+/// Converts a byte offset into a zero-based `(line, column)` pair, where the column is
+/// counted in bytes from the start of the line.
+///
+/// Returns `None` if `offset` is past the end of the document.
+pub fn offset_to_line_col(doc: &[u8], offset: usize, ending: LineEnding) -> Option<(usize, usize)> {
+    if offset > doc.len() {
+        return None;
+    }
+    let mut line = 0;
+    let mut line_start = 0;
+    for (break_start, break_len) in line_breaks(doc, ending) {
+        if break_start + break_len > offset {
+            break;
+        }
+        line += 1;
+        line_start = break_start + break_len;
+    }
+    Some((line, offset - line_start))
+}
+
+/// Converts a zero-based `(line, column)` pair back into a byte offset.
+///
+/// Returns `None` if the line doesn't exist or the column is past the end of the line
+/// (not counting the line break itself).
+pub fn line_col_to_offset(
+    doc: &[u8],
+    line: usize,
+    col: usize,
+    ending: LineEnding,
+) -> Option<usize> {
+    let mut line_start = 0;
+    let mut breaks = line_breaks(doc, ending);
+    for _ in 0..line {
+        let (break_start, break_len) = breaks.next()?;
+        line_start = break_start + break_len;
+    }
+    let line_end = breaks
+        .next()
+        .map_or(doc.len(), |(break_start, _)| break_start);
+    if line_start + col <= line_end {
+        Some(line_start + col)
+    } else {
+        None
+    }
+}
+
+/// The lines of `doc` that `op` changes, from the line of its first change to the line of its
+/// last (e.g. for showing soft locks on lines other users are editing). Empty for identity ops.
+pub fn affected_lines(doc: &[u8], op: &[Step], ending: LineEnding) -> Range<usize> {
+    let line = |offset: usize| {
+        offset_to_line_col(doc, offset.min(doc.len()), ending).map_or(0, |(line, _)| line)
+    };
+    match affected_range(op) {
+        Some(range) => line(range.start)..line(range.end) + 1,
+        None => 0..0,
+    }
+}
+
+/// An op inserting `prefix` at the start of each of the `lines` of `doc` (e.g. indenting the
+/// lines of a selection), as a single op so that it transforms as a whole against concurrent
+/// edits. Lines past the end of the document are ignored.
+pub fn indent_lines(doc: &[u8], lines: Range<usize>, prefix: &[u8], ending: LineEnding) -> Op {
+    let line_starts = std::iter::once(0)
+        .chain(line_breaks(doc, ending).map(|(break_start, break_len)| break_start + break_len));
+    let mut op = vec![];
+    let mut pos = 0;
+    for line_start in line_starts.skip(lines.start).take(lines.len()) {
+        op.push(Step::Skip(line_start - pos));
+        op.push(Step::Insert(prefix.to_vec()));
+        pos = line_start;
+    }
+    normalize(&op)
+}
+
+/// An op deleting from `cursor` to the end of its line ("kill line", Ctrl+K). The line break
+/// itself is kept, except when the cursor is already at the end of the line: then the break is
+/// deleted, joining the next line onto this one. At the end of the document it deletes nothing.
+pub fn delete_to_line_end(doc: &[u8], cursor: usize, ending: LineEnding) -> Op {
+    let cursor = cursor.min(doc.len());
+    let end = line_breaks(doc, ending)
+        .find(|&(break_start, break_len)| break_start + break_len > cursor)
+        .map_or(doc.len(), |(break_start, break_len)| {
+            if break_start > cursor {
+                break_start
+            } else {
+                break_start + break_len
+            }
+        });
+    splice(cursor, end - cursor, b"")
+}
+
+/// An op deleting from `cursor` to the end of the document.
+pub fn delete_to_doc_end(doc: &[u8], cursor: usize) -> Op {
+    let cursor = cursor.min(doc.len());
+    splice(cursor, doc.len() - cursor, b"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lf() {
+        let doc = b"ab\ncd\n\nef";
+        assert_eq!(offset_to_line_col(doc, 0, Lf), Some((0, 0)));
+        assert_eq!(offset_to_line_col(doc, 2, Lf), Some((0, 2)));
+        assert_eq!(offset_to_line_col(doc, 3, Lf), Some((1, 0)));
+        assert_eq!(offset_to_line_col(doc, 6, Lf), Some((2, 0)));
+        assert_eq!(offset_to_line_col(doc, 9, Lf), Some((3, 2)));
+        assert_eq!(offset_to_line_col(doc, 10, Lf), None);
+        assert_eq!(line_col_to_offset(doc, 1, 1, Lf), Some(4));
+        assert_eq!(line_col_to_offset(doc, 2, 0, Lf), Some(6));
+        assert_eq!(line_col_to_offset(doc, 2, 1, Lf), None);
+        assert_eq!(line_col_to_offset(doc, 4, 0, Lf), None);
+    }
+
+    #[test]
+    fn test_crlf() {
+        let doc = b"ab\r\ncd\nef\rgh";
+        // Only `\r\n` counts; the lone `\n` and `\r` are part of line 1.
+        assert_eq!(offset_to_line_col(doc, 4, CrLf), Some((1, 0)));
+        assert_eq!(offset_to_line_col(doc, 7, CrLf), Some((1, 3)));
+        assert_eq!(offset_to_line_col(doc, 12, CrLf), Some((1, 8)));
+        assert_eq!(line_col_to_offset(doc, 1, 8, CrLf), Some(12));
+        assert_eq!(line_col_to_offset(doc, 0, 3, CrLf), None);
+    }
+
+    #[test]
+    fn test_mixed_auto() {
+        let doc = b"ab\r\ncd\nef\rgh";
+        assert_eq!(offset_to_line_col(doc, 2, Auto), Some((0, 2)));
+        // Between `\r` and `\n` is still on the first line.
+        assert_eq!(offset_to_line_col(doc, 3, Auto), Some((0, 3)));
+        assert_eq!(offset_to_line_col(doc, 4, Auto), Some((1, 0)));
+        assert_eq!(offset_to_line_col(doc, 7, Auto), Some((2, 0)));
+        assert_eq!(offset_to_line_col(doc, 10, Auto), Some((3, 0)));
+        assert_eq!(offset_to_line_col(doc, 12, Auto), Some((3, 2)));
+        assert_eq!(line_col_to_offset(doc, 1, 2, Auto), Some(6));
+        assert_eq!(line_col_to_offset(doc, 3, 2, Auto), Some(12));
+        assert_eq!(line_col_to_offset(doc, 4, 0, Auto), None);
+
+        assert_eq!(offset_to_line_col(doc, 12, Lf), Some((2, 5)));
+    }
+
+    #[test]
+    fn test_affected_lines() {
+        use crate::composite_op::Step::*;
+
+        let doc = b"ab\ncd\nef\ngh";
+        assert_eq!(
+            affected_lines(doc, &[Skip(4), Insert(b"x".to_vec())], Lf),
+            1..2
+        );
+        assert_eq!(affected_lines(doc, &[Skip(3), Delete(2)], Lf), 1..2);
+        // Deleting the break joins lines 1 and 2.
+        assert_eq!(affected_lines(doc, &[Skip(5), Delete(1)], Lf), 1..3);
+        assert_eq!(
+            affected_lines(
+                doc,
+                &[Skip(1), Delete(1), Skip(6), Insert(b"x".to_vec())],
+                Lf
+            ),
+            0..3
+        );
+        assert_eq!(affected_lines(doc, &[Skip(4)], Lf), 0..0);
+    }
+
+    #[test]
+    fn test_indent_lines() {
+        use crate::composite_op::{apply_with_scratch, Step::*};
+
+        let mut doc = b"fn f() {\nlet x = 1;\nx + 1\n}\n".to_vec();
+        let op = indent_lines(&doc, 1..4, b"  ", Lf);
+        assert_eq!(
+            op,
+            vec![
+                Skip(9),
+                Insert(b"  ".to_vec()),
+                Skip(11),
+                Insert(b"  ".to_vec()),
+                Skip(6),
+                Insert(b"  ".to_vec()),
+            ]
+        );
+        apply_with_scratch(&mut doc, &op, &mut vec![]);
+        assert_eq!(doc, b"fn f() {\n  let x = 1;\n  x + 1\n  }\n".to_vec());
+
+        assert_eq!(
+            indent_lines(b"a\nb", 1..5, b"> ", Lf),
+            vec![Skip(2), Insert(b"> ".to_vec())]
+        );
+        assert!(indent_lines(b"a\nb", 1..1, b"> ", Lf).is_empty());
+    }
+
+    #[test]
+    fn test_delete_to_line_end() {
+        use crate::composite_op::Step::*;
+
+        let doc = b"ab\r\ncd\nef";
+        // Mid-line: up to the break.
+        assert_eq!(delete_to_line_end(doc, 1, CrLf), vec![Skip(1), Delete(1)]);
+        assert_eq!(delete_to_line_end(doc, 4, Lf), vec![Skip(4), Delete(2)]);
+        // At the end of a line: the break.
+        assert_eq!(delete_to_line_end(doc, 2, CrLf), vec![Skip(2), Delete(2)]);
+        assert_eq!(delete_to_line_end(doc, 3, Auto), vec![Skip(3), Delete(1)]);
+        assert_eq!(delete_to_line_end(doc, 6, Lf), vec![Skip(6), Delete(1)]);
+        // The last line.
+        assert_eq!(delete_to_line_end(doc, 7, Lf), vec![Skip(7), Delete(2)]);
+        assert_eq!(delete_to_line_end(doc, 9, Lf), vec![]);
+        assert_eq!(delete_to_line_end(doc, 20, Lf), vec![]);
+    }
+
+    #[test]
+    fn test_delete_to_doc_end() {
+        use crate::composite_op::Step::*;
+
+        let doc = b"ab\ncd";
+        assert_eq!(delete_to_doc_end(doc, 1), vec![Skip(1), Delete(4)]);
+        assert_eq!(delete_to_doc_end(doc, 0), vec![Delete(5)]);
+        assert_eq!(delete_to_doc_end(doc, 5), vec![]);
+        assert_eq!(delete_to_doc_end(doc, 9), vec![]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let doc = b"x\r\n\r\ny\n\rz\r";
+        for &ending in &[Lf, CrLf, Auto] {
+            for offset in 0..=doc.len() {
+                let (line, col) = offset_to_line_col(doc, offset, ending).unwrap();
+                // Offsets inside a `\r\n` break map to a column past the line's end.
+                if let Some(back) = line_col_to_offset(doc, line, col, ending) {
+                    assert_eq!(back, offset, "{:?} at {}", ending, offset);
+                }
+            }
+        }
+    }
+}
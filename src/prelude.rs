@@ -0,0 +1,17 @@
+//! The commonly used items, for glob importing.
+//!
+//! ```
+//! use ot::prelude::*;
+//! ```
+//!
+//! Composite ops are the default representation, so the prelude re-exports `composite_op`'s
+//! items together with the `Step` and `Side` variants. The `single_op` and `rich_text` modules
+//! define items with the same names and have to be used by path.
+
+pub use crate::client::Client;
+pub use crate::composite_op::{
+    apply, compose, normalize, transform, try_apply, ApplyError, Doc, Op, Side, Side::*, Step,
+    Step::*,
+};
+pub use crate::envelope::Envelope;
+pub use crate::server::Server;
@@ -0,0 +1,496 @@
+use crate::composite_op::{self, insert_tiebreak, OpCategory};
+use std::cmp::min;
+use std::cmp::Ordering::Greater;
+use std::collections::BTreeMap;
+
+/// Formatting attributes, e.g. `"bold" => Some("true")`.
+///
+/// In a `Retain` step a `None` value removes the attribute; characters in a `Doc` only ever
+/// carry `Some` values.
+///
+/// Keys iterate in sorted order, independently of the order they were inserted in, so that equal
+/// ops encode (see `codec::encode_rich_text`) and hash the same.
+pub type Attributes = BTreeMap<String, Option<String>>;
+
+/// A document where every byte carries its own formatting.
+pub type Doc = Vec<(u8, Attributes)>;
+
+pub type Chunk = Vec<u8>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Step {
+    /// Keeps `n` characters, applying the attribute changes to them if present.
+    Retain(usize, Option<Attributes>),
+    /// Inserts unformatted text.
+    Insert(Chunk),
+    Delete(usize),
+}
+use Step::*;
+
+pub type Op = Vec<Step>;
+
+/// The op that changes nothing.
+pub const fn identity() -> Op {
+    Vec::new()
+}
+
+/// The category of `op`: whether it changes the text, formatting, both or nothing.
+pub fn categorize(op: &[Step]) -> OpCategory {
+    let text = op.iter().any(|step| match step {
+        Insert(s) => !s.is_empty(),
+        Delete(n) => *n > 0,
+        Retain(_, _) => false,
+    });
+    let format = op.iter().any(|step| match step {
+        Retain(n, Some(attributes)) => *n > 0 && !attributes.is_empty(),
+        _ => false,
+    });
+    match (text, format) {
+        (false, false) => OpCategory::CursorOnly,
+        (true, false) => OpCategory::TextEdit,
+        (false, true) => OpCategory::FormatOnly,
+        (true, true) => OpCategory::Mixed,
+    }
+}
+
+fn format(attributes: &mut Attributes, changes: &Attributes) {
+    for (key, value) in changes {
+        match value {
+            Some(_) => {
+                attributes.insert(key.clone(), value.clone());
+            }
+            None => {
+                attributes.remove(key);
+            }
+        }
+    }
+}
+
+pub fn apply(doc: &mut Doc, op: &[Step]) {
+    let mut result = Vec::with_capacity(doc.len());
+    let mut old = std::mem::replace(doc, vec![]).into_iter();
+    for step in op {
+        match step {
+            Retain(n, changes) => {
+                for _ in 0..*n {
+                    let (c, mut attributes) = old.next().expect("retain past end of document");
+                    if let Some(changes) = changes {
+                        format(&mut attributes, changes);
+                    }
+                    result.push((c, attributes));
+                }
+            }
+            Insert(s) => {
+                result.extend(s.iter().map(|&c| (c, Attributes::new())));
+            }
+            Delete(n) => {
+                for _ in 0..*n {
+                    old.next().expect("delete past end of document");
+                }
+            }
+        }
+    }
+    result.extend(old);
+    *doc = result;
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+use Side::*;
+
+impl From<&Side> for composite_op::Side {
+    fn from(side: &Side) -> Self {
+        match side {
+            Left => composite_op::Side::Left,
+            Right => composite_op::Side::Right,
+        }
+    }
+}
+
+fn step_len(step: &Step) -> usize {
+    match step {
+        Retain(n, _) | Delete(n) => *n,
+        Insert(s) => s.len(),
+    }
+}
+
+static INFINITE_RETAIN: Step = Retain(usize::MAX, None);
+
+/// Walks the steps of an op, allowing them to be consumed partially.
+///
+/// Past the end of the op it yields an infinite plain `Retain`.
+struct StepCursor<'a> {
+    steps: std::slice::Iter<'a, Step>,
+    head: Option<Step>,
+}
+
+impl<'a> StepCursor<'a> {
+    fn new(op: &'a [Step]) -> Self {
+        let mut cursor = StepCursor {
+            steps: op.iter(),
+            head: None,
+        };
+        cursor.advance();
+        cursor
+    }
+
+    fn advance(&mut self) {
+        self.head = self.steps.by_ref().find(|step| step_len(step) > 0).cloned();
+    }
+
+    fn is_done(&self) -> bool {
+        self.head.is_none()
+    }
+
+    fn peek(&self) -> &Step {
+        self.head.as_ref().unwrap_or(&INFINITE_RETAIN)
+    }
+
+    /// Takes at most `n` units off the current step.
+    fn take(&mut self, n: usize) -> Step {
+        let step = match self.head.take() {
+            None => return Retain(n, None),
+            Some(step) => step,
+        };
+        if n >= step_len(&step) {
+            self.advance();
+            return step;
+        }
+        let (taken, rest) = match step {
+            Retain(m, attributes) => (Retain(n, attributes.clone()), Retain(m - n, attributes)),
+            Delete(m) => (Delete(n), Delete(m - n)),
+            Insert(s) => (Insert(s[..n].to_vec()), Insert(s[n..].to_vec())),
+        };
+        self.head = Some(rest);
+        taken
+    }
+}
+
+/// Appends `step` to `op`, merging it into the previous step where possible.
+fn push_step(op: &mut Op, step: Step) {
+    let step = match step {
+        Retain(n, Some(ref attributes)) if attributes.is_empty() => Retain(n, None),
+        step => step,
+    };
+    if step_len(&step) == 0 {
+        return;
+    }
+    match (op.last_mut(), &step) {
+        (Some(Retain(n, a)), Retain(m, b)) if a == b => *n = n.saturating_add(*m),
+        (Some(Delete(n)), Delete(m)) => *n = n.saturating_add(*m),
+        (Some(Insert(s)), Insert(t)) => s.extend_from_slice(t),
+        _ => op.push(step),
+    }
+}
+
+/// Resolves formatting of the same characters by both ops: the `Left` op wins conflicting
+/// attributes.
+fn transform_attributes(
+    attributes1: &Option<Attributes>,
+    attributes2: &Option<Attributes>,
+    side: &Side,
+) -> Option<Attributes> {
+    match (attributes1, attributes2, side) {
+        (Some(attributes1), Some(attributes2), Right) => Some(
+            attributes1
+                .iter()
+                .filter(|(key, _)| !attributes2.contains_key(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        ),
+        _ => attributes1.clone(),
+    }
+}
+
+/// Takes two operations defined on the same initial document,
+/// and returns an operation equivalent to `op1` which can be applied after `op2`.
+///
+/// When both ops insert at the same position, or set the same attribute on the same
+/// character, `op1` wins if `side` is `Left`.
+///
+/// Never panics, even for ops that aren't valid on any document.
+pub fn transform(op1: &[Step], op2: &[Step], side: Side) -> Op {
+    let mut a = StepCursor::new(op1);
+    let mut b = StepCursor::new(op2);
+    let mut result = vec![];
+    // Position in the original document.
+    let mut pos: usize = 0;
+    while !(a.is_done() && b.is_done()) {
+        match (a.peek(), b.peek()) {
+            (Insert(_), Insert(_)) if insert_tiebreak(pos, pos, (&side).into()) == Greater => {
+                let n = step_len(b.peek());
+                b.take(n);
+                push_step(&mut result, Retain(n, None));
+            }
+            (Insert(_), _) => push_step(&mut result, a.take(usize::MAX)),
+            (_, Insert(s)) => {
+                let n = s.len();
+                b.take(n);
+                push_step(&mut result, Retain(n, None));
+            }
+            _ => {
+                let n = min(step_len(a.peek()), step_len(b.peek()));
+                pos = pos.saturating_add(n);
+                match (a.take(n), b.take(n)) {
+                    // The text was already deleted by `op2`.
+                    (_, Delete(_)) => {}
+                    (Delete(_), _) => push_step(&mut result, Delete(n)),
+                    (Retain(_, attributes1), Retain(_, attributes2)) => push_step(
+                        &mut result,
+                        Retain(n, transform_attributes(&attributes1, &attributes2, &side)),
+                    ),
+                    // Inserts are handled above.
+                    _ => push_step(&mut result, Retain(n, None)),
+                }
+            }
+        }
+    }
+    if let Some(Retain(_, None)) = result.last() {
+        result.pop();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(pairs: &[(&str, Option<&str>)]) -> Attributes {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.map(|v| v.to_string())))
+            .collect()
+    }
+
+    fn plain(s: &[u8]) -> Doc {
+        s.iter().map(|&c| (c, Attributes::new())).collect()
+    }
+
+    #[test]
+    fn test_apply_format() {
+        let mut doc = plain(b"abcd");
+        let bold = attrs(&[("bold", Some("true"))]);
+        apply(
+            &mut doc,
+            &[Retain(1, None), Retain(2, Some(bold.clone())), Delete(1)],
+        );
+        assert_eq!(
+            doc,
+            vec![
+                (b'a', Attributes::new()),
+                (b'b', bold.clone()),
+                (b'c', bold.clone())
+            ]
+        );
+
+        apply(
+            &mut doc,
+            &[
+                Retain(2, Some(attrs(&[("bold", None)]))),
+                Insert(b"x".to_vec()),
+            ],
+        );
+        assert_eq!(
+            doc,
+            vec![
+                (b'a', Attributes::new()),
+                (b'b', Attributes::new()),
+                (b'x', Attributes::new()),
+                (b'c', bold)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_transform_overlapping_formats() {
+        let doc = plain(b"abcdef");
+        // op1 makes "bcd" bold and red, op2 makes "cde" bold and blue.
+        let op1 = vec![
+            Retain(1, None),
+            Retain(
+                3,
+                Some(attrs(&[("bold", Some("1")), ("color", Some("red"))])),
+            ),
+        ];
+        let op2 = vec![
+            Retain(2, None),
+            Retain(
+                3,
+                Some(attrs(&[("bold", Some("1")), ("color", Some("blue"))])),
+            ),
+        ];
+
+        let mut doc1 = doc.clone();
+        apply(&mut doc1, &op1);
+        apply(&mut doc1, &transform(&op2, &op1, Right));
+
+        let mut doc2 = doc;
+        apply(&mut doc2, &op2);
+        apply(&mut doc2, &transform(&op1, &op2, Left));
+
+        assert_eq!(doc1, doc2);
+        // op1 wins the overlap.
+        assert_eq!(doc1[3].1.get("color"), Some(&Some("red".to_string())));
+        assert_eq!(doc1[4].1.get("color"), Some(&Some("blue".to_string())));
+    }
+
+    #[test]
+    fn test_categorize() {
+        let bold = Some(attrs(&[("bold", Some("true"))]));
+        assert_eq!(
+            categorize(&[Retain(1, None), Insert(b"x".to_vec())]),
+            OpCategory::TextEdit
+        );
+        assert_eq!(
+            categorize(&[Retain(1, None), Retain(2, bold.clone())]),
+            OpCategory::FormatOnly
+        );
+        assert_eq!(
+            categorize(&[Retain(3, None), Retain(2, Some(Attributes::new()))]),
+            OpCategory::CursorOnly
+        );
+        assert_eq!(categorize(&[Retain(2, bold), Delete(1)]), OpCategory::Mixed);
+    }
+
+    #[test]
+    fn test_identity() {
+        let op = vec![Retain(1, Some(attrs(&[("bold", Some("true"))]))), Delete(1)];
+        assert_eq!(transform(&op, &identity(), Left), op);
+        assert_eq!(transform(&identity(), &op, Left), identity());
+        let mut doc = plain(b"abc");
+        apply(&mut doc, &identity());
+        assert_eq!(doc, plain(b"abc"));
+    }
+
+    #[test]
+    fn test_transform_huge_lengths() {
+        let max = usize::MAX;
+        let op1 = vec![Retain(max, None), Retain(1, None), Delete(max)];
+        let op2 = vec![Delete(max), Delete(max), Insert(b"x".to_vec())];
+        assert_eq!(
+            transform(&op1, &op2, Left),
+            vec![Retain(1, None), Delete(1)]
+        );
+        assert_eq!(
+            transform(&op2, &op1, Right),
+            vec![Delete(max), Insert(b"x".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_insert_tie_matches_composite_op() {
+        use crate::composite_op::Step as Plain;
+
+        let op1 = vec![Retain(1, None), Delete(1), Insert(b"x".to_vec())];
+        let op2 = vec![Retain(1, None), Insert(b"yz".to_vec())];
+        let plain_op1 = vec![
+            Plain::Skip(1),
+            Plain::Delete(1),
+            Plain::Insert(b"x".to_vec()),
+        ];
+        let plain_op2 = vec![Plain::Skip(1), Plain::Insert(b"yz".to_vec())];
+        for side in vec![Left, Right] {
+            let mut plain_doc = b"abc".to_vec();
+            crate::composite_op::apply(&mut plain_doc, &plain_op2);
+            let transformed =
+                crate::composite_op::transform(&plain_op1, &plain_op2, (&side).into());
+            crate::composite_op::apply(&mut plain_doc, &transformed);
+
+            let mut doc = plain(b"abc");
+            apply(&mut doc, &op2);
+            apply(&mut doc, &transform(&op1, &op2, side));
+
+            let text: Vec<u8> = doc.iter().map(|(c, _)| *c).collect();
+            assert_eq!(text, plain_doc);
+        }
+    }
+
+    use proptest::prelude::*;
+
+    fn arb_attributes() -> impl Strategy<Value = Attributes> {
+        // Value 0 stands for removing the attribute.
+        proptest::collection::vec((0..3u8, 0..3u8), 0..3).prop_map(|pairs| {
+            pairs
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = if value == 0 {
+                        None
+                    } else {
+                        Some(format!("v{}", value))
+                    };
+                    (format!("k{}", key), value)
+                })
+                .collect()
+        })
+    }
+
+    fn arb_doc() -> impl Strategy<Value = Doc> {
+        proptest::collection::vec((any::<u8>(), arb_attributes()), 0..20).prop_map(|chars| {
+            chars
+                .into_iter()
+                .map(|(c, attributes)| {
+                    let attributes = attributes
+                        .into_iter()
+                        .filter(|(_, v)| v.is_some())
+                        .collect();
+                    (c, attributes)
+                })
+                .collect()
+        })
+    }
+
+    fn valid_op_for(len: usize) -> impl Strategy<Value = Op> {
+        proptest::collection::vec((0..4u8, 1..5usize, any::<Chunk>(), arb_attributes()), 0..8)
+            .prop_map(move |steps| {
+                let mut remaining = len;
+                let mut op = vec![];
+                for (kind, n, s, attributes) in steps {
+                    let n = min(n, remaining);
+                    match kind {
+                        0 if n > 0 => {
+                            remaining -= n;
+                            op.push(Retain(n, None));
+                        }
+                        1 if n > 0 => {
+                            remaining -= n;
+                            op.push(Retain(n, Some(attributes)));
+                        }
+                        2 if n > 0 => {
+                            remaining -= n;
+                            op.push(Delete(n));
+                        }
+                        _ => op.push(Insert(s)),
+                    }
+                }
+                op
+            })
+    }
+
+    fn doc_and_two_valid_ops() -> impl Strategy<Value = (Doc, Op, Op)> {
+        arb_doc().prop_flat_map(|doc| {
+            (valid_op_for(doc.len()), valid_op_for(doc.len()))
+                .prop_map(move |(op1, op2)| (doc.clone(), op1, op2))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn transform_property_1((doc, op1, op2) in doc_and_two_valid_ops()) {
+            let mut doc1 = doc.clone();
+            let transformed_op2 = transform(&op2, &op1, Right);
+            apply(&mut doc1, &op1);
+            apply(&mut doc1, &transformed_op2);
+
+            let mut doc2 = doc.clone();
+            let transformed_op1 = transform(&op1, &op2, Left);
+            apply(&mut doc2, &op2);
+            apply(&mut doc2, &transformed_op1);
+
+            prop_assert_eq!(doc1, doc2, "\ntransformed_op1 = {:?},\ntransformed_op2 = {:?}\n", transformed_op1, transformed_op2);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use crate::composite_op::{
+    affected_range, apply_with_scratch, input_len, len_delta, normalize, transform, ApplyError,
+    Doc, Op, Side, Side::*, Step, Step::Skip,
+};
+use crate::envelope::{Envelope, OpId};
+use std::collections::HashMap;
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum ServerError {
+    /// The op was made against a revision the server hasn't reached yet.
+    UnknownRevision(usize),
+    /// The op has more steps than the server accepts.
+    TooManySteps { steps: usize, max_steps: usize },
+    /// The op, transformed to the current revision, doesn't apply to the document.
+    InvalidOp(ApplyError),
+    /// The op's signature doesn't match it or its author, see `receive_signed`.
+    #[cfg(feature = "ed25519-dalek")]
+    InvalidSignature,
+}
+
+/// The central authority of a collaborative session. Puts incoming ops in a total order,
+/// transforming each one against everything that happened since the revision it was made
+/// against.
+pub struct Server {
+    doc: Doc,
+    history: Vec<Op>,
+    scratch: Doc,
+    max_steps: usize,
+    /// The highest `seq` received from each client, to recognize redelivered envelopes.
+    last_seqs: HashMap<u64, u64>,
+    /// The document before each op in `history`, for the convergence check.
+    #[cfg(all(feature = "verify-convergence", debug_assertions))]
+    snapshots: Vec<Doc>,
+}
+
+impl Server {
+    pub fn new(doc: Doc) -> Self {
+        Server {
+            doc,
+            history: vec![],
+            scratch: vec![],
+            max_steps: usize::MAX,
+            last_seqs: HashMap::new(),
+            #[cfg(all(feature = "verify-convergence", debug_assertions))]
+            snapshots: vec![],
+        }
+    }
+
+    /// Makes `receive` reject ops with more than `max_steps` steps, so that a client can't make
+    /// the server spend arbitrarily long transforming a single op. Unlimited by default.
+    pub fn with_max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn document(&self) -> &Doc {
+        &self.doc
+    }
+
+    /// Number of ops applied so far.
+    pub fn revision(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn history(&self) -> &[Op] {
+        &self.history
+    }
+
+    /// Receives `op` made against `revision` and applies it. Returns the transformed op, which
+    /// should be broadcast to the other clients.
+    ///
+    /// Ops already in the history win insert ties against the incoming op.
+    pub fn receive(&mut self, revision: usize, op: impl Into<Op>) -> Result<Op, ServerError> {
+        let op = op.into();
+        if revision > self.history.len() {
+            return Err(ServerError::UnknownRevision(revision));
+        }
+        if op.len() > self.max_steps {
+            return Err(ServerError::TooManySteps {
+                steps: op.len(),
+                max_steps: self.max_steps,
+            });
+        }
+        #[cfg(all(feature = "verify-convergence", debug_assertions))]
+        let original = op.clone();
+
+        let op = transform_against_history(op, &self.history[revision..], transform);
+        let input_len = input_len(&op);
+        if input_len > self.doc.len() {
+            return Err(ServerError::InvalidOp(ApplyError::OutOfBounds {
+                input_len,
+                doc_len: self.doc.len(),
+            }));
+        }
+        #[cfg(all(feature = "verify-convergence", debug_assertions))]
+        {
+            self.verify(revision, &original);
+            self.snapshots.push(self.doc.clone());
+        }
+
+        apply_with_scratch(&mut self.doc, &op, &mut self.scratch);
+        self.history.push(op.clone());
+        Ok(op)
+    }
+
+    /// Like `receive`, for an op in an envelope. The returned envelope keeps the op's id, so that
+    /// the client that sent it can recognize it in the broadcast.
+    ///
+    /// An envelope whose id was already received (e.g. replayed after a reconnect) isn't applied
+    /// again: the server's state doesn't change and the returned envelope carries an identity op.
+    /// Since a client sends its ops in `seq` order, that's any envelope with a `seq` no higher than
+    /// the last one received from the same client, so the server only remembers one `seq` per
+    /// client.
+    pub fn receive_envelope(
+        &mut self,
+        revision: usize,
+        envelope: Envelope,
+    ) -> Result<Envelope, ServerError> {
+        if self.is_applied(envelope.id) {
+            return Ok(Envelope {
+                id: envelope.id,
+                op: Op::identity(),
+            });
+        }
+        let op = self.receive(revision, envelope.op)?;
+        self.last_seqs.insert(envelope.id.client, envelope.id.seq);
+        Ok(Envelope {
+            id: envelope.id,
+            op,
+        })
+    }
+
+    fn is_applied(&self, id: OpId) -> bool {
+        self.last_seqs
+            .get(&id.client)
+            .map_or(false, |&last_seq| id.seq <= last_seq)
+    }
+
+    /// Like `receive`, for a signed op. Rejects the op without applying it if the signature
+    /// doesn't verify.
+    #[cfg(feature = "ed25519-dalek")]
+    pub fn receive_signed(
+        &mut self,
+        revision: usize,
+        signed: &crate::signing::SignedOp,
+    ) -> Result<Op, ServerError> {
+        if !crate::signing::verify(signed) {
+            return Err(ServerError::InvalidSignature);
+        }
+        self.receive(revision, signed.op.clone())
+    }
+
+    /// Checks TP1 for every transform `receive` is about to do.
+    #[cfg(all(feature = "verify-convergence", debug_assertions))]
+    fn verify(&self, revision: usize, op: &Op) {
+        let mut op = op.clone();
+        for (snapshot, concurrent) in self.snapshots[revision..]
+            .iter()
+            .zip(&self.history[revision..])
+        {
+            verify::check_tp1(snapshot, &op, concurrent, transform);
+            op = transform(&op, concurrent, Right);
+        }
+    }
+}
+
+/// Transforms `op` against each op in `history` in turn.
+///
+/// Ops that only touch the document after everything `op` changes leave it as it is, so they're
+/// skipped without calling `transform`, and ops that only touch it before `op`'s first change
+/// just shift `op` by their length change. In a long history most ops are usually like that.
+fn transform_against_history(
+    op: Op,
+    history: &[Op],
+    mut transform: impl FnMut(&[Step], &[Step], Side) -> Op,
+) -> Op {
+    // Normalized, so that skipping `transform` gives the same result as calling it, and so that
+    // the result doesn't depend on whether there was anything to transform against.
+    let mut op = normalize(&op);
+    for concurrent in history {
+        if let (Some(range), Some(concurrent_range)) =
+            (affected_range(&op), affected_range(concurrent))
+        {
+            if concurrent_range.end < range.start {
+                // Normalized and not at the start of the document, so it starts with a skip.
+                if let Some(Skip(n)) = op.0.first_mut() {
+                    *n = (*n as isize + len_delta(concurrent)) as usize;
+                }
+            } else if concurrent_range.start <= range.end {
+                op = transform(&op, concurrent, Right);
+            }
+        }
+    }
+    op
+}
+
+/// Runtime convergence check, enabled in debug builds by the `verify-convergence` feature.
+#[cfg(all(feature = "verify-convergence", debug_assertions))]
+pub mod verify {
+    use crate::composite_op::{apply_with_scratch, Op, Side, Step};
+
+    /// Panics with a detailed message if `transform` doesn't satisfy TP1 for `op1` and `op2`,
+    /// both defined on `doc`.
+    pub fn check_tp1(
+        doc: &[u8],
+        op1: &[Step],
+        op2: &[Step],
+        transform: impl Fn(&[Step], &[Step], Side) -> Op,
+    ) {
+        let transformed_op1 = transform(op1, op2, Side::Right);
+        let transformed_op2 = transform(op2, op1, Side::Left);
+
+        let mut scratch = vec![];
+        let mut doc1 = doc.to_vec();
+        apply_with_scratch(&mut doc1, op2, &mut scratch);
+        apply_with_scratch(&mut doc1, &transformed_op1, &mut scratch);
+        let mut doc2 = doc.to_vec();
+        apply_with_scratch(&mut doc2, op1, &mut scratch);
+        apply_with_scratch(&mut doc2, &transformed_op2, &mut scratch);
+
+        assert!(
+            doc1 == doc2,
+            "transform violates TP1\ndoc = {:?}\nop1 = {:?}\nop2 = {:?}\ntransformed_op1 = {:?}\ntransformed_op2 = {:?}\nop2 then op1 = {:?}\nop1 then op2 = {:?}",
+            doc, op1, op2, transformed_op1, transformed_op2, doc1, doc2,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+
+    #[test]
+    fn test_receive_concurrent_ops() {
+        let mut server = Server::new(b"abc".to_vec());
+        let op1 = server
+            .receive(0, vec![Skip(1), Insert(b"x".to_vec())])
+            .unwrap();
+        assert_eq!(op1, vec![Skip(1), Insert(b"x".to_vec())]);
+
+        // Made against revision 0, concurrently with the op above.
+        let op2 = server.receive(0, vec![Skip(2), Delete(1)]).unwrap();
+        assert_eq!(op2, vec![Skip(3), Delete(1)]);
+        assert_eq!(server.document(), b"axb");
+        assert_eq!(server.revision(), 2);
+    }
+
+    #[test]
+    fn test_receive_unknown_revision() {
+        let mut server = Server::new(vec![]);
+        assert_eq!(
+            server.receive(1, vec![Insert(b"x".to_vec())]),
+            Err(ServerError::UnknownRevision(1))
+        );
+    }
+
+    #[test]
+    fn test_receive_too_many_steps() {
+        let mut server = Server::new(b"abc".to_vec()).with_max_steps(4);
+        let op = vec![Skip(1), Insert(b"x".to_vec()), Skip(1), Delete(1)];
+        assert!(server.receive(0, op).is_ok());
+
+        let op = vec![Insert(b"x".to_vec()); 5];
+        assert_eq!(
+            server.receive(1, op),
+            Err(ServerError::TooManySteps {
+                steps: 5,
+                max_steps: 4
+            })
+        );
+        assert_eq!(server.revision(), 1);
+    }
+
+    #[test]
+    fn test_receive_out_of_bounds() {
+        let mut server = Server::new(b"abc".to_vec());
+        assert!(server.receive(0, vec![Delete(2)]).is_ok());
+
+        // Deletes the "c", which is still there, but a byte past it too.
+        assert_eq!(
+            server.receive(0, vec![Skip(2), Delete(2)]),
+            Err(ServerError::InvalidOp(ApplyError::OutOfBounds {
+                input_len: 2,
+                doc_len: 1
+            }))
+        );
+        assert_eq!(server.document(), b"c");
+        assert_eq!(server.revision(), 1);
+    }
+
+    #[test]
+    fn test_receive_duplicate_envelope() {
+        let mut server = Server::new(b"abc".to_vec());
+        let envelope = Envelope {
+            id: OpId { client: 1, seq: 0 },
+            op: Op(vec![Skip(1), Insert(b"x".to_vec())]),
+        };
+        let broadcast = server.receive_envelope(0, envelope.clone()).unwrap();
+        assert_eq!(broadcast, envelope);
+
+        let replayed = server.receive_envelope(0, envelope.clone()).unwrap();
+        assert_eq!(replayed.id, envelope.id);
+        assert!(replayed.op.is_empty());
+        assert_eq!(server.document(), b"axbc");
+        assert_eq!(server.revision(), 1);
+
+        // Later ops from the same client, and ops from other clients, still apply.
+        for (client, seq) in &[(1, 1), (2, 0)] {
+            let envelope = Envelope {
+                id: OpId {
+                    client: *client,
+                    seq: *seq,
+                },
+                op: Op(vec![Insert(b"y".to_vec())]),
+            };
+            let revision = server.revision();
+            assert_eq!(
+                server.receive_envelope(revision, envelope.clone()),
+                Ok(envelope)
+            );
+        }
+        assert_eq!(server.document(), b"yyaxbc");
+        // An older one, replayed after the newer one.
+        let replayed = server.receive_envelope(0, envelope).unwrap();
+        assert!(replayed.op.is_empty());
+        assert_eq!(server.revision(), 3);
+    }
+
+    #[test]
+    fn test_disjoint_history_is_not_transformed() {
+        let history: Vec<Op> = (0..100)
+            .map(|i| Op(vec![Skip(10 + i), Insert(b"x".to_vec())]))
+            .chain(vec![Op(vec![Delete(1)])])
+            .collect();
+        let mut calls = 0;
+        let op = transform_against_history(
+            Op(vec![Skip(5), Delete(2), Skip(1)]),
+            &history,
+            |op1, op2, side| {
+                calls += 1;
+                transform(op1, op2, side)
+            },
+        );
+        assert_eq!(op, vec![Skip(4), Delete(2)]);
+        assert_eq!(calls, 0);
+
+        let expected = history
+            .iter()
+            .fold(Op(vec![Skip(5), Delete(2)]), |op, concurrent| {
+                transform(&op, concurrent, Right)
+            });
+        assert_eq!(op, expected);
+    }
+
+    #[test]
+    fn test_history_before_op_only_shifts_it() {
+        // Alternately inserting and deleting before the op, and editing far after it.
+        let history: Vec<Op> = (0..1000)
+            .map(|i| match i % 3 {
+                0 => Op(vec![Skip(i % 7), Insert(b"xyz".to_vec())]),
+                1 => Op(vec![Skip(i % 5), Delete(2)]),
+                _ => Op(vec![Skip(100_000), Delete(1)]),
+            })
+            .collect();
+        let op = Op(vec![Skip(50), Insert(b"a".to_vec()), Skip(2), Delete(1)]);
+        let mut calls = 0;
+        let result = transform_against_history(op.clone(), &history, |op1, op2, side| {
+            calls += 1;
+            transform(op1, op2, side)
+        });
+        assert_eq!(calls, 0);
+
+        let expected = history
+            .iter()
+            .fold(op, |op, concurrent| transform(&op, concurrent, Right));
+        assert_eq!(result, expected);
+
+        // Reaching into the op still transforms it.
+        let history = vec![Op(vec![Skip(49), Delete(2)])];
+        let result = transform_against_history(
+            Op(vec![Skip(50), Insert(b"a".to_vec())]),
+            &history,
+            transform,
+        );
+        assert_eq!(result, vec![Skip(49), Insert(b"a".to_vec())]);
+    }
+
+    #[test]
+    fn test_empty_history_normalizes() {
+        let op = Op(vec![Skip(1), Skip(2), Delete(1), Skip(3)]);
+        assert_eq!(
+            transform_against_history(op.clone(), &[], transform),
+            normalize(&op)
+        );
+    }
+
+    #[cfg(feature = "ed25519-dalek")]
+    #[test]
+    fn test_receive_signed() {
+        use crate::signing::sign;
+        use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+        let secret = SecretKey::from_bytes(&[1; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let keypair = Keypair { secret, public };
+
+        let mut server = Server::new(b"abc".to_vec());
+        let mut signed = sign(Op(vec![Skip(1), Insert(b"x".to_vec())]), &keypair);
+        signed.op = Op(vec![Delete(3)]);
+        assert_eq!(
+            server.receive_signed(0, &signed),
+            Err(ServerError::InvalidSignature)
+        );
+        assert_eq!(server.revision(), 0);
+
+        let signed = sign(Op(vec![Skip(1), Insert(b"x".to_vec())]), &keypair);
+        assert!(server.receive_signed(0, &signed).is_ok());
+        assert_eq!(server.document(), b"axbc");
+    }
+
+    #[cfg(all(feature = "verify-convergence", debug_assertions))]
+    mod verify_convergence {
+        use super::*;
+        use crate::composite_op::Side;
+
+        #[test]
+        fn test_receive_with_check() {
+            let mut server = Server::new(b"abc".to_vec());
+            server.receive(0, vec![Skip(1), Delete(1)]).unwrap();
+            server
+                .receive(0, vec![Skip(1), Insert(b"x".to_vec())])
+                .unwrap();
+            assert_eq!(server.document(), b"axc");
+        }
+
+        #[test]
+        #[should_panic(expected = "transform violates TP1")]
+        fn test_check_catches_broken_transform() {
+            // Doesn't shift positions past the other op's insert.
+            let broken = |op1: &[Step], _: &[Step], _: Side| Op(op1.to_vec());
+            verify::check_tp1(
+                b"abc",
+                &[Skip(2), Delete(1)],
+                &[Insert(b"x".to_vec())],
+                broken,
+            );
+        }
+    }
+}
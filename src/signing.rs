@@ -0,0 +1,67 @@
+//! Ops signed by their authors, for sessions where peers don't trust each other. Enabled by the
+//! `ed25519-dalek` feature.
+//!
+//! Signatures cover `codec::canonical_bytes` of the op, so equivalent spellings of an op verify
+//! alike, and the signed bytes stay the same as long as the encoding does.
+
+use crate::codec::canonical_bytes;
+use crate::composite_op::Op;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+
+/// An op together with its author's signature of it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SignedOp {
+    pub op: Op,
+    pub author: PublicKey,
+    pub sig: Signature,
+}
+
+/// Signs `op` with the author's `keypair`.
+pub fn sign(op: Op, keypair: &Keypair) -> SignedOp {
+    let sig = keypair.sign(&canonical_bytes(&op));
+    SignedOp {
+        op,
+        author: keypair.public,
+        sig,
+    }
+}
+
+/// Whether `signed.sig` is `signed.author`'s signature of `signed.op`.
+pub fn verify(signed: &SignedOp) -> bool {
+    signed
+        .author
+        .verify(&canonical_bytes(&signed.op), &signed.sig)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+    use ed25519_dalek::SecretKey;
+
+    fn keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signed = sign(Op(vec![Skip(1), Insert(b"x".to_vec())]), &keypair(1));
+        assert!(verify(&signed));
+
+        // An equivalent spelling of the same op.
+        let mut respelled = signed.clone();
+        respelled.op = Op(vec![Skip(1), Insert(b"x".to_vec()), Skip(3)]);
+        assert!(verify(&respelled));
+
+        let mut tampered = signed.clone();
+        tampered.op = Op(vec![Skip(1), Insert(b"y".to_vec())]);
+        assert!(!verify(&tampered));
+
+        let mut impersonated = signed;
+        impersonated.author = keypair(2).public;
+        assert!(!verify(&impersonated));
+    }
+}
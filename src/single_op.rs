@@ -1,15 +1,144 @@
+use crate::composite_op::{self, insert_tiebreak, ConflictInfo};
 use std::cmp::Ordering::*;
+use std::convert::TryFrom;
+use std::ops::Range;
 
+/// A position in the document, as used by `transform`. Inserting before a position moves it
+/// `plus(1)`, deleting before it `minus(1)`.
+///
+/// Implemented for `usize` and `Pos32` (see `CompactOp`); other totally ordered position schemes
+/// (e.g. fractional indices) can implement it too. Neither `plus` nor `minus` may panic, so that
+/// `transform` can't either: they should saturate instead of overflowing. The checked versions
+/// return `None` instead, for `transform_checked`.
+pub trait Position: Ord + Copy {
+    /// The type of an insert's `num_deletes` next to positions of this type.
+    type Count: DeleteCount;
+
+    fn plus(self, n: usize) -> Self;
+    fn minus(self, n: usize) -> Self;
+    fn checked_plus(self, n: usize) -> Option<Self>;
+    fn checked_minus(self, n: usize) -> Option<Self>;
+}
+
+impl Position for usize {
+    type Count = usize;
+
+    fn plus(self, n: usize) -> usize {
+        self.saturating_add(n)
+    }
+
+    fn minus(self, n: usize) -> usize {
+        self.saturating_sub(n)
+    }
+
+    fn checked_plus(self, n: usize) -> Option<usize> {
+        self.checked_add(n)
+    }
+
+    fn checked_minus(self, n: usize) -> Option<usize> {
+        self.checked_sub(n)
+    }
+}
+
+/// A `u32` position, enough for documents under 4GB. `n` is checked for both the conversion and
+/// the arithmetic: `plus` and `minus` saturate on overflow like `usize` does.
+///
+/// A newtype rather than `u32` itself, so that integer literals in `Op`s still default to `usize`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct Pos32(pub u32);
+
+impl Position for Pos32 {
+    type Count = Pos32;
+
+    fn plus(self, n: usize) -> Pos32 {
+        self.checked_plus(n).unwrap_or(Pos32(u32::MAX))
+    }
+
+    fn minus(self, n: usize) -> Pos32 {
+        self.checked_minus(n).unwrap_or(Pos32(0))
+    }
+
+    fn checked_plus(self, n: usize) -> Option<Pos32> {
+        let n = u32::try_from(n).ok()?;
+        self.0.checked_add(n).map(Pos32)
+    }
+
+    fn checked_minus(self, n: usize) -> Option<Pos32> {
+        let n = u32::try_from(n).ok()?;
+        self.0.checked_sub(n).map(Pos32)
+    }
+}
+
+impl From<Pos32> for usize {
+    fn from(pos: Pos32) -> usize {
+        pos.0 as usize
+    }
+}
+
+/// The type of an insert's `num_deletes`: `usize`, or `Pos32` in a `CompactOp`.
+pub trait DeleteCount: Ord + Copy {
+    fn get(self) -> usize;
+    /// One more, or `None` on overflow.
+    fn checked_incr(self) -> Option<Self>;
+}
+
+impl DeleteCount for usize {
+    fn get(self) -> usize {
+        self
+    }
+
+    fn checked_incr(self) -> Option<usize> {
+        self.checked_add(1)
+    }
+}
+
+impl DeleteCount for Pos32 {
+    fn get(self) -> usize {
+        self.into()
+    }
+
+    fn checked_incr(self) -> Option<Pos32> {
+        self.checked_plus(1)
+    }
+}
+
+/// `Insert(index, num_deletes, c)` inserts `c` at `index`; `num_deletes` counts the deletes
+/// before `index` that the insert has been transformed past, to order concurrent inserts by their
+/// position in the original document.
 #[derive(Eq, PartialEq, Debug, Clone)]
-pub enum Op {
-    Insert(usize, usize, u8),
-    Delete(usize),
+pub enum Op<P = usize, C = usize> {
+    Insert(P, C, u8),
+    Delete(P),
     Noop,
 }
 use Op::*;
 
+impl<P, C> Op<P, C> {
+    /// The op that changes nothing.
+    pub const fn identity() -> Self {
+        Noop
+    }
+}
+
+/// An op with `u32` positions and delete counts, half the size of `Op` (e.g. in long op logs).
+///
+/// `transform` saturates a position pushed past `u32::MAX` instead of failing, so its result is
+/// only right for documents shorter than that; `transform_checked` reports the overflow instead.
+pub type CompactOp = Op<Pos32, Pos32>;
+
 pub type Doc = Vec<u8>;
 
+/// The `Op` at the same positions, e.g. for applying a `CompactOp` from a log with `apply`.
+impl From<&CompactOp> for Op {
+    fn from(op: &CompactOp) -> Op {
+        match *op {
+            Insert(index, num_deletes, c) => Insert(index.into(), num_deletes.into(), c),
+            Delete(index) => Delete(index.into()),
+            Noop => Noop,
+        }
+    }
+}
+
 pub fn apply(doc: &mut Doc, op: &Op) {
     match *op {
         Insert(index, _, c) => {
@@ -22,7 +151,27 @@ pub fn apply(doc: &mut Doc, op: &Op) {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// The op for Backspace with the cursor at `cursor`: deletes the character before the cursor,
+/// if there is one.
+pub fn delete_before(cursor: usize) -> Option<Op> {
+    if cursor > 0 {
+        Some(Delete(cursor - 1))
+    } else {
+        None
+    }
+}
+
+/// The op for the forward Delete key with the cursor at `cursor` in a document of `doc_len`
+/// characters: deletes the character after the cursor, if there is one.
+pub fn delete_after(cursor: usize, doc_len: usize) -> Option<Op> {
+    if cursor < doc_len {
+        Some(Delete(cursor))
+    } else {
+        None
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum Side {
     Left,
     Right,
@@ -30,6 +179,15 @@ pub enum Side {
 
 use Side::*;
 
+impl From<Side> for composite_op::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Left => composite_op::Side::Left,
+            Right => composite_op::Side::Right,
+        }
+    }
+}
+
 /// Takes two operations defined on the same initial document,
 /// and returns an operation equivalent to `op1` which can be applied after `op2`.
 ///
@@ -43,23 +201,73 @@ use Side::*;
 /// ```ignore
 /// { apply(doc, op2); apply(doc, transform(op1, op2, Left)); }
 /// ```
-pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
-    match *op1 {
+///
+/// The two transforms of a pair must use opposite sides, otherwise concurrent inserts at the
+/// same position diverge. `transform_pair` takes care of that.
+///
+/// Never panics, even for ops that aren't valid on any document.
+pub fn transform<P: Position>(
+    op1: &Op<P, P::Count>,
+    op2: &Op<P, P::Count>,
+    side: Side,
+) -> Op<P, P::Count> {
+    transform_arithmetic(op1, op2, side, false).expect("saturating arithmetic doesn't overflow")
+}
+
+/// Like `transform`, but returns `None` instead of saturating if a position or delete count
+/// overflows (e.g. a `CompactOp` pushed past `u32::MAX`).
+pub fn transform_checked<P: Position>(
+    op1: &Op<P, P::Count>,
+    op2: &Op<P, P::Count>,
+    side: Side,
+) -> Option<Op<P, P::Count>> {
+    transform_arithmetic(op1, op2, side, true)
+}
+
+/// `transform` with checked arithmetic if `checked`, saturating otherwise.
+fn transform_arithmetic<P: Position>(
+    op1: &Op<P, P::Count>,
+    op2: &Op<P, P::Count>,
+    side: Side,
+    checked: bool,
+) -> Option<Op<P, P::Count>> {
+    let plus = |pos: P, n: usize| {
+        if checked {
+            pos.checked_plus(n)
+        } else {
+            Some(pos.plus(n))
+        }
+    };
+    let minus = |pos: P, n: usize| {
+        if checked {
+            pos.checked_minus(n)
+        } else {
+            Some(pos.minus(n))
+        }
+    };
+    if *op2 == Noop {
+        return Some(op1.clone());
+    }
+    let op = match *op1 {
         Insert(index, num_deletes, c) => {
             let mut num_deletes = num_deletes;
             let new_index = match *op2 {
-                Insert(index2, num_deletes_2, _) => {
-                    match ((index2 + num_deletes_2).cmp(&(index + num_deletes)), side) {
-                        (Less, _) => index + 1,
-                        (Equal, Left) => index,
-                        (Equal, Right) => index + 1,
-                        (Greater, _) => index,
-                    }
-                }
+                Insert(index2, num_deletes_2, _) => match insert_tiebreak(
+                    plus(index, num_deletes.get())?,
+                    plus(index2, num_deletes_2.get())?,
+                    side.into(),
+                ) {
+                    Less => index,
+                    _ => plus(index, 1)?,
+                },
                 Delete(index2) => {
                     if index2 < index {
-                        num_deletes += 1;
-                        index - 1
+                        num_deletes = match num_deletes.checked_incr() {
+                            Some(incremented) => incremented,
+                            None if checked => return None,
+                            None => num_deletes,
+                        };
+                        minus(index, 1)?
                     } else {
                         index
                     }
@@ -72,17 +280,17 @@ pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
             let new_index = match *op2 {
                 Insert(index2, _, _) => {
                     if index2 <= index {
-                        index + 1
+                        plus(index, 1)?
                     } else {
                         index
                     }
                 }
                 Delete(index2) => {
                     match index2.cmp(&index) {
-                        Less => index - 1,
+                        Less => minus(index, 1)?,
                         Equal => {
                             // Both ops deleted the same character
-                            return Noop;
+                            return Some(Noop);
                         }
                         Greater => index,
                     }
@@ -92,6 +300,142 @@ pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
             Delete(new_index)
         }
         Noop => Noop,
+    };
+    Some(op)
+}
+
+fn with_deletes_seen(op: &Op, deletes_seen: usize) -> Op {
+    match *op {
+        Insert(index, num_deletes, c) => Insert(index, num_deletes.saturating_add(deletes_seen), c),
+        _ => op.clone(),
+    }
+}
+
+/// Like `transform`, for ops generated on different forks of the document.
+///
+/// `deletes_seen_1` and `deletes_seen_2` are the numbers of deletes before each op's position
+/// that its author applied since the common ancestor, before generating the op. They go into
+/// the inserts' `num_deletes`, so that concurrent inserts are ordered by their position in the
+/// common ancestor. The result carries the adjusted `num_deletes`.
+pub fn transform_from_forks(
+    op1: &Op,
+    deletes_seen_1: usize,
+    op2: &Op,
+    deletes_seen_2: usize,
+    side: Side,
+) -> Op {
+    transform(
+        &with_deletes_seen(op1, deletes_seen_1),
+        &with_deletes_seen(op2, deletes_seen_2),
+        side,
+    )
+}
+
+/// Like `transform`, but also tells whether `op1` was a delete cancelled by `op2` deleting the
+/// same character. A single delete is never partially cancelled.
+pub fn transform_with_conflict_info(op1: &Op, op2: &Op, side: Side) -> (Op, ConflictInfo) {
+    let info = match (op1, op2) {
+        (Delete(index1), Delete(index2)) if index1 == index2 => ConflictInfo::FullyCancelled,
+        _ => ConflictInfo::Untouched,
+    };
+    (transform(op1, op2, side), info)
+}
+
+/// Transforms two concurrent operations against each other, returning `(op1', op2')` such that
+/// applying `op1` then `op2'` is equivalent to applying `op2` then `op1'`.
+///
+/// `op1` wins ties between inserts at the same position.
+pub fn transform_pair(op1: &Op, op2: &Op) -> (Op, Op) {
+    (transform(op1, op2, Left), transform(op2, op1, Right))
+}
+
+/// Like `transform_pair`, but treats identical ops as the same edit received twice (e.g. a client
+/// resending after a network retry): both transform to `Noop`, so the edit takes effect once.
+///
+/// Note that this also merges two users concurrently typing the same character at the same
+/// position into one character, which `transform_pair` would keep as two.
+pub fn transform_pair_dedup(op1: &Op, op2: &Op) -> (Op, Op) {
+    if op1 == op2 {
+        return (Noop, Noop);
+    }
+    transform_pair(op1, op2)
+}
+
+/// Inverse of `transform`: given `op1_prime = transform(op1, op2, side)`, recovers `op1`.
+///
+/// Returns `None` if `op1` can't be determined. That only happens when `op2` is a `Delete`:
+/// `op1_prime` being a `Noop` might come from a `Noop` or from the same delete, and an insert
+/// right at the deleted position might have been there already or have been moved there from
+/// right after the deleted character.
+pub fn untransform(op1_prime: &Op, op2: &Op, side: Side) -> Option<Op> {
+    // `transform` moves ops by at most one position, so `op1` is one of these.
+    let candidates = match *op1_prime {
+        Insert(index, num_deletes, c) => vec![
+            Some(Insert(index, num_deletes, c)),
+            index
+                .checked_sub(1)
+                .map(|index| Insert(index, num_deletes, c)),
+            num_deletes
+                .checked_sub(1)
+                .map(|num_deletes| Insert(index.saturating_add(1), num_deletes, c)),
+        ],
+        Delete(index) => vec![
+            Some(Delete(index)),
+            index.checked_sub(1).map(Delete),
+            Some(Delete(index.saturating_add(1))),
+        ],
+        Noop => vec![Some(Noop), Some(op2.clone())],
+    };
+    let mut matching: Vec<Op> = vec![];
+    for op1 in candidates.into_iter().flatten() {
+        if transform(&op1, op2, side) == *op1_prime && !matching.contains(&op1) {
+            matching.push(op1);
+        }
+    }
+    if matching.len() == 1 {
+        matching.pop()
+    } else {
+        None
+    }
+}
+
+/// Where a cursor at `pos` ends up once `op` is applied.
+///
+/// The cursor moves the way an insert typed at it would under `transform` (including the
+/// `num_deletes` tie-break), so the two stay together: for `Left` it stays in front of an insert
+/// at the same position, for `Right` it moves past it.
+pub fn transform_position(pos: usize, op: &Op, side: Side) -> usize {
+    match transform(&Insert(pos, 0, 0), op, side) {
+        Insert(index, _, _) => index,
+        _ => unreachable!("transform keeps inserts as inserts"),
+    }
+}
+
+/// Like `transform_pair`, also telling whether both ops deleted the same character, cancelling
+/// each other out (e.g. for conflict statistics).
+pub fn transform_pair_single(op1: &Op, op2: &Op) -> (Op, Op, bool) {
+    let (op1_prime, info) = transform_with_conflict_info(op1, op2, Left);
+    let op2_prime = transform(op2, op1, Right);
+    (op1_prime, op2_prime, info == ConflictInfo::FullyCancelled)
+}
+
+/// Like `transform`, but never lets an `Insert` land strictly inside one of the `atomics`
+/// spans (given in coordinates of the document after `op2`). Such an insert is moved to the
+/// start of the span for `Left` and to its end for `Right`.
+pub fn transform_with_atomics(op1: &Op, op2: &Op, side: Side, atomics: &[Range<usize>]) -> Op {
+    let snap_to_end = side == Right;
+    match transform(op1, op2, side) {
+        Insert(index, num_deletes, c) => {
+            let index = atomics
+                .iter()
+                .find(|span| span.start < index && index < span.end)
+                .map_or(
+                    index,
+                    |span| if snap_to_end { span.end } else { span.start },
+                );
+            Insert(index, num_deletes, c)
+        }
+        op => op,
     }
 }
 
@@ -99,6 +443,136 @@ pub fn transform(op1: &Op, op2: &Op, side: Side) -> Op {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_identity() {
+        let op = Insert(1, 0, b'x');
+        assert_eq!(transform(&op, &Op::identity(), Left), op);
+        assert_eq!(transform(&Op::identity(), &op, Left), Op::identity());
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &Op::identity());
+        assert_eq!(doc, b"abc");
+    }
+
+    #[test]
+    fn test_transform_with_conflict_info() {
+        assert_eq!(
+            transform_with_conflict_info(&Delete(2), &Delete(2), Left),
+            (Noop, ConflictInfo::FullyCancelled)
+        );
+        assert_eq!(
+            transform_with_conflict_info(&Delete(2), &Delete(1), Left),
+            (Delete(1), ConflictInfo::Untouched)
+        );
+        assert_eq!(
+            transform_with_conflict_info(&Insert(2, 0, b'x'), &Delete(2), Left),
+            (Insert(2, 0, b'x'), ConflictInfo::Untouched)
+        );
+    }
+
+    #[test]
+    fn test_transform_pair_dedup() {
+        let op = Insert(1, 0, b'x');
+        let (op1_prime, op2_prime) = transform_pair_dedup(&op, &op);
+        assert_eq!(op1_prime, Noop);
+        assert_eq!(op2_prime, Noop);
+        for second in &[op1_prime, op2_prime] {
+            let mut doc = b"abc".to_vec();
+            apply(&mut doc, &op);
+            apply(&mut doc, second);
+            assert_eq!(doc, b"axbc");
+        }
+        assert_eq!(transform_pair(&op, &op), (op, Insert(2, 0, b'x')));
+
+        assert_eq!(
+            transform_pair_dedup(&Insert(1, 0, b'x'), &Insert(1, 0, b'y')),
+            transform_pair(&Insert(1, 0, b'x'), &Insert(1, 0, b'y'))
+        );
+    }
+
+    #[test]
+    fn test_transform_pair_single() {
+        assert_eq!(
+            transform_pair_single(&Delete(1), &Delete(1)),
+            (Noop, Noop, true)
+        );
+        assert_eq!(
+            transform_pair_single(&Delete(1), &Delete(2)),
+            (Delete(1), Delete(1), false)
+        );
+        assert_eq!(
+            transform_pair_single(&Insert(1, 0, b'x'), &Delete(1)),
+            (Insert(1, 0, b'x'), Delete(2), false)
+        );
+    }
+
+    #[test]
+    fn test_insert_vs_delete_around_position() {
+        let insert = Insert(2, 0, b'x');
+        for (delete, expected) in &[
+            (Delete(1), &b"axcd"[..]),
+            (Delete(2), b"abxd"),
+            (Delete(3), b"abxc"),
+        ] {
+            let (insert_prime, delete_prime) = transform_pair(&insert, delete);
+            let mut doc1 = b"abcd".to_vec();
+            apply(&mut doc1, &insert);
+            apply(&mut doc1, &delete_prime);
+            let mut doc2 = b"abcd".to_vec();
+            apply(&mut doc2, delete);
+            apply(&mut doc2, &insert_prime);
+            assert_eq!(doc1, *expected, "{:?}", delete);
+            assert_eq!(doc2, *expected, "{:?}", delete);
+        }
+        // Only a delete strictly before the insert moves it.
+        assert_eq!(transform(&insert, &Delete(1), Left), Insert(1, 1, b'x'));
+        assert_eq!(transform(&insert, &Delete(2), Left), insert);
+        assert_eq!(transform(&Delete(2), &insert, Left), Delete(3));
+    }
+
+    #[test]
+    fn test_untransform() {
+        let op2 = Insert(1, 0, b'y');
+        for op1 in &[
+            Insert(0, 0, b'x'),
+            Insert(1, 0, b'x'),
+            Insert(2, 0, b'x'),
+            Delete(0),
+            Delete(1),
+        ] {
+            for &side in &[Left, Right] {
+                let op1_prime = transform(op1, &op2, side);
+                assert_eq!(untransform(&op1_prime, &op2, side), Some(op1.clone()));
+            }
+        }
+        assert_eq!(
+            untransform(&Insert(1, 1, b'x'), &Delete(0), Left),
+            Some(Insert(2, 0, b'x'))
+        );
+        assert_eq!(untransform(&Noop, &Insert(0, 0, b'y'), Left), Some(Noop));
+        assert_eq!(untransform(&Noop, &Noop, Left), Some(Noop));
+        // The delete might have been cancelled by `op2`, or have been a `Noop` all along.
+        assert_eq!(untransform(&Noop, &Delete(1), Left), None);
+        // Could be `Insert(1, 1, _)` unchanged or `Insert(2, 0, _)` moved back.
+        assert_eq!(untransform(&Insert(1, 1, b'x'), &Delete(1), Left), None);
+    }
+
+    #[test]
+    fn test_transform_position() {
+        // Insert before, at and after the cursor.
+        assert_eq!(transform_position(2, &Insert(1, 0, b'x'), Left), 3);
+        assert_eq!(transform_position(2, &Insert(2, 0, b'x'), Left), 2);
+        assert_eq!(transform_position(2, &Insert(2, 0, b'x'), Right), 3);
+        assert_eq!(transform_position(2, &Insert(3, 0, b'x'), Right), 2);
+        // The insert was made after a delete before it, so it's further right in the ancestor.
+        assert_eq!(transform_position(2, &Insert(2, 1, b'x'), Right), 2);
+
+        // Delete before the cursor, of the character after it, and further away.
+        assert_eq!(transform_position(2, &Delete(1), Left), 1);
+        assert_eq!(transform_position(2, &Delete(2), Left), 2);
+        assert_eq!(transform_position(2, &Delete(3), Right), 2);
+        assert_eq!(transform_position(2, &Noop, Right), 2);
+    }
+
     #[test]
     fn test_apply_insert() {
         let mut doc = b"abc".to_vec();
@@ -113,6 +587,327 @@ mod tests {
         assert_eq!(doc, b"ac");
     }
 
+    #[test]
+    fn test_delete_before() {
+        assert_eq!(delete_before(0), None);
+
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &delete_before(2).unwrap());
+        assert_eq!(doc, b"ac");
+    }
+
+    #[test]
+    fn test_delete_after() {
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &delete_after(0, 3).unwrap());
+        assert_eq!(doc, b"bc");
+        apply(&mut doc, &delete_after(1, 2).unwrap());
+        assert_eq!(doc, b"b");
+        assert_eq!(delete_after(1, 1), None);
+    }
+
+    #[test]
+    fn test_transform_same_side_diverges() {
+        let op1 = Insert(0, 0, b'a');
+        let op2 = Insert(0, 0, b'b');
+
+        let mut doc1 = vec![];
+        apply(&mut doc1, &op1);
+        apply(&mut doc1, &transform(&op2, &op1, Left));
+        let mut doc2 = vec![];
+        apply(&mut doc2, &op2);
+        apply(&mut doc2, &transform(&op1, &op2, Left));
+        assert_ne!(doc1, doc2);
+
+        let (op1_prime, op2_prime) = transform_pair(&op1, &op2);
+        let mut doc1 = vec![];
+        apply(&mut doc1, &op1);
+        apply(&mut doc1, &op2_prime);
+        let mut doc2 = vec![];
+        apply(&mut doc2, &op2);
+        apply(&mut doc2, &op1_prime);
+        assert_eq!(doc1, doc2);
+        assert_eq!(doc1, b"ab");
+    }
+
+    /// A toy fractional position `numerator / denominator`.
+    #[derive(Clone, Copy, Debug)]
+    struct Frac(u64, u64);
+
+    impl PartialEq for Frac {
+        fn eq(&self, other: &Frac) -> bool {
+            self.cmp(other) == Equal
+        }
+    }
+
+    impl Eq for Frac {}
+
+    impl PartialOrd for Frac {
+        fn partial_cmp(&self, other: &Frac) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl Ord for Frac {
+        fn cmp(&self, other: &Frac) -> std::cmp::Ordering {
+            (self.0 * other.1).cmp(&(other.0 * self.1))
+        }
+    }
+
+    impl Position for Frac {
+        type Count = usize;
+
+        fn plus(self, n: usize) -> Frac {
+            Frac(self.0 + n as u64 * self.1, self.1)
+        }
+
+        fn minus(self, n: usize) -> Frac {
+            Frac(self.0 - n as u64 * self.1, self.1)
+        }
+
+        fn checked_plus(self, n: usize) -> Option<Frac> {
+            Some(self.plus(n))
+        }
+
+        fn checked_minus(self, n: usize) -> Option<Frac> {
+            Some(self.minus(n))
+        }
+    }
+
+    #[test]
+    fn test_transform_extreme_positions() {
+        let max = usize::MAX;
+        assert_eq!(
+            transform(&Insert(max, 0, b'x'), &Insert(0, 0, b'y'), Left),
+            Insert(max, 0, b'x')
+        );
+        assert_eq!(
+            transform(&Insert(1, max, b'x'), &Insert(0, max, b'y'), Right),
+            Insert(2, max, b'x')
+        );
+        assert_eq!(
+            transform(&Insert(1, max, b'x'), &Delete(0), Left),
+            Insert(0, max, b'x')
+        );
+        assert_eq!(
+            transform(&Delete(max), &Insert(0, 0, b'y'), Left),
+            Delete(max)
+        );
+        assert_eq!(
+            transform_from_forks(&Insert(0, 1, b'x'), max, &Delete(0), 0, Left),
+            Insert(0, max, b'x')
+        );
+    }
+
+    #[test]
+    fn test_transform_usize_positions() {
+        let op1: Op<usize> = Insert(2, 0, b'x');
+        assert_eq!(
+            transform(&op1, &Insert(1, 0, b'y'), Left),
+            Insert(3, 0, b'x')
+        );
+        assert_eq!(transform(&op1, &Delete(0), Left), Insert(1, 1, b'x'));
+    }
+
+    #[test]
+    fn test_transform_u32_positions() {
+        assert_eq!(std::mem::size_of::<CompactOp>(), 12);
+        assert_eq!(std::mem::size_of::<Op>(), 3 * std::mem::size_of::<usize>());
+
+        let op1: CompactOp = Insert(Pos32(2), Pos32(0), b'x');
+        assert_eq!(
+            transform(&op1, &Insert(Pos32(1), Pos32(0), b'y'), Left),
+            Insert(Pos32(3), Pos32(0), b'x')
+        );
+        assert_eq!(
+            transform(&op1, &Delete(Pos32(0)), Left),
+            Insert(Pos32(1), Pos32(1), b'x')
+        );
+        let mut doc = b"abc".to_vec();
+        apply(&mut doc, &Op::from(&op1));
+        assert_eq!(doc, b"abxc");
+
+        let max = Pos32(u32::MAX);
+        let zero = Pos32(0);
+        assert_eq!(
+            transform(
+                &Insert(Pos32(u32::MAX - 1), zero, b'x'),
+                &Insert(zero, zero, b'y'),
+                Left
+            ),
+            Insert(max, zero, b'x')
+        );
+        // Saturates instead of overflowing, also for counts that don't fit in a `u32`.
+        assert_eq!(
+            transform(&Insert(max, zero, b'x'), &Insert(zero, zero, b'y'), Left),
+            Insert(max, zero, b'x')
+        );
+        assert_eq!(max.plus(usize::MAX), max);
+        assert_eq!(Pos32(1).minus(usize::MAX), zero);
+        assert_eq!(
+            transform(
+                &Insert(Pos32(1), max, b'x'),
+                &Insert(zero, max, b'y'),
+                Right
+            ),
+            Insert(Pos32(2), max, b'x')
+        );
+        assert_eq!(
+            transform(&Insert(Pos32(1), max, b'x'), &Delete(zero), Left),
+            Insert(zero, max, b'x')
+        );
+
+        // The checked transform reports the overflows instead.
+        assert_eq!(
+            transform_checked(&op1, &Insert(Pos32(1), zero, b'y'), Left),
+            Some(Insert(Pos32(3), zero, b'x'))
+        );
+        assert_eq!(
+            transform_checked(&Insert(max, zero, b'x'), &Insert(zero, zero, b'y'), Left),
+            None
+        );
+        assert_eq!(
+            transform_checked(&Insert(Pos32(1), max, b'x'), &Delete(zero), Left),
+            None
+        );
+        assert_eq!(
+            transform_checked(&Delete(max), &Insert(zero, zero, b'y'), Left),
+            None
+        );
+        assert_eq!(max.checked_plus(1), None);
+        assert_eq!(zero.checked_minus(1), None);
+    }
+
+    #[test]
+    fn test_transform_fractional_positions() {
+        let op1 = Insert(Frac(3, 2), 0, b'x');
+        assert_eq!(
+            transform(&op1, &Insert(Frac(1, 2), 0, b'y'), Left),
+            Insert(Frac(5, 2), 0, b'x')
+        );
+        assert_eq!(
+            transform(&op1, &Insert(Frac(2, 1), 0, b'y'), Left),
+            Insert(Frac(3, 2), 0, b'x')
+        );
+        // 6/4 is the same position as 3/2, so the side decides.
+        assert_eq!(
+            transform(&op1, &Insert(Frac(6, 4), 0, b'y'), Right),
+            Insert(Frac(5, 2), 0, b'x')
+        );
+        assert_eq!(
+            transform(&Delete(Frac(5, 2)), &Delete(Frac(1, 1)), Left),
+            Delete(Frac(3, 2))
+        );
+        assert_eq!(
+            transform(&Delete(Frac(5, 2)), &Delete(Frac(10, 4)), Left),
+            Noop
+        );
+    }
+
+    #[test]
+    fn test_adjacent_deletes() {
+        assert_eq!(transform(&Delete(5), &Delete(4), Left), Delete(4));
+        assert_eq!(transform(&Delete(4), &Delete(5), Right), Delete(4));
+        assert_eq!(transform(&Delete(4), &Delete(4), Right), Noop);
+
+        // Three adjacent deletes, applied in two different orders.
+        let mut doc1 = b"abcdefg".to_vec();
+        let mut doc2 = doc1.clone();
+        let (d3, d4, d5) = (Delete(3), Delete(4), Delete(5));
+        let d4_after_3 = transform(&d4, &d3, Right);
+        let d5_after_3_4 = transform(&transform(&d5, &d3, Right), &d4_after_3, Right);
+        for op in &[d3.clone(), d4_after_3, d5_after_3_4] {
+            apply(&mut doc1, op);
+        }
+        let d4_after_5 = transform(&d4, &d5, Right);
+        let d3_after_5_4 = transform(&transform(&d3, &d5, Right), &d4_after_5, Right);
+        for op in &[d5, d4_after_5, d3_after_5_4] {
+            apply(&mut doc2, op);
+        }
+        assert_eq!(doc1, b"abcg");
+        assert_eq!(doc2, b"abcg");
+    }
+
+    #[test]
+    fn test_transform_against_noop() {
+        for op in &[Insert(1, 0, b'x'), Insert(2, 1, b'y'), Delete(1), Noop] {
+            assert_eq!(transform(op, &Noop, Left), *op);
+            assert_eq!(transform(op, &Noop, Right), *op);
+        }
+    }
+
+    #[test]
+    fn test_transform_with_atomics_snaps_to_boundary() {
+        let atomics = [2..5];
+        let op = Insert(3, 0, b'x');
+        assert_eq!(
+            transform_with_atomics(&op, &Noop, Left, &atomics),
+            Insert(2, 0, b'x')
+        );
+        assert_eq!(
+            transform_with_atomics(&op, &Noop, Right, &atomics),
+            Insert(5, 0, b'x')
+        );
+        // Boundaries themselves are fine.
+        let op = Insert(2, 0, b'x');
+        assert_eq!(
+            transform_with_atomics(&op, &Noop, Right, &atomics),
+            Insert(2, 0, b'x')
+        );
+    }
+
+    #[test]
+    fn test_transform_with_atomics_after_shift() {
+        // `op2` shifts the insert from 2 to 3, which is inside the span.
+        let atomics = [2..5];
+        let op1 = Insert(2, 0, b'x');
+        let op2 = Insert(0, 0, b'y');
+        assert_eq!(transform(&op1, &op2, Left), Insert(3, 0, b'x'));
+        assert_eq!(
+            transform_with_atomics(&op1, &op2, Left, &atomics),
+            Insert(2, 0, b'x')
+        );
+        assert_eq!(
+            transform_with_atomics(&op1, &op2, Right, &atomics),
+            Insert(5, 0, b'x')
+        );
+        assert_eq!(
+            transform_with_atomics(&Delete(3), &op2, Left, &atomics),
+            Delete(4)
+        );
+    }
+
+    #[test]
+    fn test_concurrent_first_inserts_on_empty_doc() {
+        let op1 = Insert(0, 0, b'a');
+        let op2 = Insert(0, 0, b'b');
+
+        let mut doc1 = vec![];
+        apply(&mut doc1, &op1);
+        apply(&mut doc1, &transform(&op2, &op1, Right));
+
+        let mut doc2 = vec![];
+        apply(&mut doc2, &op2);
+        apply(&mut doc2, &transform(&op1, &op2, Left));
+
+        assert_eq!(doc1, b"ab");
+        assert_eq!(doc2, b"ab");
+    }
+
+    #[test]
+    fn test_transform_noop_on_empty_doc() {
+        let op = Insert(0, 0, b'a');
+        assert_eq!(transform(&op, &Noop, Left), op);
+        assert_eq!(transform(&Noop, &op, Right), Noop);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_apply_delete_on_empty_doc() {
+        apply(&mut vec![], &Delete(0));
+    }
+
+    use crate::test_support::format_divergence;
     use proptest::prelude::*;
 
     fn valid_op_for(doc: &Doc) -> impl Strategy<Value = Op> {
@@ -123,44 +918,180 @@ mod tests {
             //
             // But this could possibly break in the more general case, where we can generate new
             // operations from arbitrary fork points. We _could_ give them proper num_deletes, but
-            // that would actually require tombstones...
+            // that would actually require tombstones... See `transform_from_forks` and
+            // `forked_ops_converge` below for that case.
             1 => (0..=doc.len(), any::<u8>()).prop_map(|(index, c)| Insert(index, 0, c)),
             (doc.len() > 0) as u32 => (0..doc.len()).prop_map(|index| Delete(index)),
         ]
     }
 
+    /// Like `any::<Doc>()`, but generates the empty document much more often.
+    fn arb_doc() -> impl Strategy<Value = Doc> {
+        prop_oneof![
+            1 => Just(vec![]),
+            3 => any::<Doc>(),
+        ]
+    }
+
     fn doc_and_two_valid_ops() -> impl Strategy<Value = (Doc, Op, Op)> {
-        any::<Doc>().prop_flat_map(|doc| {
+        arb_doc().prop_flat_map(|doc| {
             (valid_op_for(&doc), valid_op_for(&doc))
                 .prop_map(move |(op1, op2)| (doc.clone(), op1, op2))
         })
     }
 
     fn doc_and_3_valid_ops() -> impl Strategy<Value = (Doc, Op, Op, Op)> {
-        any::<Doc>().prop_flat_map(|doc| {
+        arb_doc().prop_flat_map(|doc| {
             (valid_op_for(&doc), valid_op_for(&doc), valid_op_for(&doc))
                 .prop_map(move |(op1, op2, op3)| (doc.clone(), op1, op2, op3))
         })
     }
 
+    /// A document, a concurrent delete on each of two forks, and an insert made on each fork
+    /// after its delete.
+    fn doc_and_forked_ops() -> impl Strategy<Value = (Doc, usize, usize, Op, Op)> {
+        any::<Doc>()
+            .prop_filter("need something to delete", |doc| !doc.is_empty())
+            .prop_flat_map(|doc| {
+                let len = doc.len();
+                (
+                    Just(doc),
+                    0..len,
+                    0..len,
+                    (0..len, any::<u8>()).prop_map(|(index, c)| Insert(index, 0, c)),
+                    (0..len, any::<u8>()).prop_map(|(index, c)| Insert(index, 0, c)),
+                )
+            })
+    }
+
+    fn deletes_seen(delete_index: usize, op: &Op) -> usize {
+        match *op {
+            Insert(index, _, _) if delete_index < index => 1,
+            _ => 0,
+        }
+    }
+
+    fn is_valid_for(doc: &[u8], op: &Op) -> bool {
+        match *op {
+            Insert(index, _, _) => index <= doc.len(),
+            Delete(index) => index < doc.len(),
+            Noop => true,
+        }
+    }
+
+    /// Applies three concurrent ops in both orders of the first two, checking that every
+    /// transformed op is valid where it's applied and that the results converge.
+    fn check_three_ops(doc: Doc, op1: Op, op2: Op, op3: Op) -> Result<(), TestCaseError> {
+        let mut doc1 = doc.clone();
+        let transformed_op2 = transform(&op2, &op1, Right);
+        apply(&mut doc1, &op1);
+        prop_assert!(is_valid_for(&doc1, &transformed_op2));
+        apply(&mut doc1, &transformed_op2);
+
+        let mut doc2 = doc;
+        let transformed_op1 = transform(&op1, &op2, Left);
+        apply(&mut doc2, &op2);
+        prop_assert!(is_valid_for(&doc2, &transformed_op1));
+        apply(&mut doc2, &transformed_op1);
+
+        let op3_transformed_by_1_2 =
+            transform(&transform(&op3, &op1, Right), &transformed_op2, Right);
+        prop_assert!(is_valid_for(&doc1, &op3_transformed_by_1_2));
+        apply(&mut doc1, &op3_transformed_by_1_2);
+        let op3_transformed_by_2_1 =
+            transform(&transform(&op3, &op2, Right), &transformed_op1, Right);
+        prop_assert!(is_valid_for(&doc2, &op3_transformed_by_2_1));
+        apply(&mut doc2, &op3_transformed_by_2_1);
+
+        prop_assert_eq!(
+            &doc1,
+            &doc2,
+            "\n{}\nops1 = {:?}\nops2 = {:?}\n",
+            format_divergence(&doc1, &doc2),
+            &[op1, transformed_op2, op3_transformed_by_1_2],
+            &[op2, transformed_op1, op3_transformed_by_2_1],
+        );
+        Ok(())
+    }
+
+    /// Ops at positions within 2 of each other, mostly deletes.
+    fn doc_and_3_clustered_ops() -> impl Strategy<Value = (Doc, Op, Op, Op)> {
+        any::<Doc>()
+            .prop_filter("need something to delete", |doc| !doc.is_empty())
+            .prop_flat_map(|doc| {
+                let len = doc.len();
+                let clustered_op = move |base: usize| {
+                    prop_oneof![
+                        3 => (0..3usize).prop_map(move |offset| Delete((base + offset).min(len - 1))),
+                        1 => (0..3usize, any::<u8>())
+                            .prop_map(move |(offset, c)| Insert((base + offset).min(len), 0, c)),
+                    ]
+                };
+                (0..len).prop_flat_map(move |base| {
+                    (
+                        Just(doc.clone()),
+                        clustered_op(base),
+                        clustered_op(base),
+                        clustered_op(base),
+                    )
+                })
+            })
+    }
+
     proptest! {
         #[test]
-        fn transform_property_1((doc, op1, op2) in doc_and_two_valid_ops()) {
-            let mut doc1 = doc.clone();
-            let transformed_op2 = transform(&op2, &op1, Right);
-            apply(&mut doc1, &op1);
-            apply(&mut doc1, &transformed_op2);
+        fn side_only_affects_insert_ties((_, op1, op2) in doc_and_two_valid_ops()) {
+            let left = transform(&op1, &op2, Left);
+            let right = transform(&op1, &op2, Right);
+            match (&op1, &op2) {
+                (Insert(index1, num_deletes_1, c), Insert(index2, num_deletes_2, _))
+                    if index1 + num_deletes_1 == index2 + num_deletes_2 =>
+                {
+                    prop_assert_eq!(&left, &op1);
+                    prop_assert_eq!(right, Insert(index1 + 1, *num_deletes_1, *c));
+                }
+                _ => prop_assert_eq!(left, right),
+            }
+        }
 
-            let mut doc2 = doc.clone();
-            let transformed_op1 = transform(&op1, &op2, Left);
-            apply(&mut doc2, &op2);
-            apply(&mut doc2, &transformed_op1);
+        #[test]
+        fn untransform_inverts_transform((_, op1, op2) in doc_and_two_valid_ops(), left in any::<bool>()) {
+            let side = if left { Left } else { Right };
+            let op1_prime = transform(&op1, &op2, side);
+            match untransform(&op1_prime, &op2, side) {
+                Some(untransformed) => prop_assert_eq!(untransformed, op1),
+                // Only ambiguous after a delete.
+                None => prop_assert!(matches!(op2, Delete(_))),
+            }
+        }
+
+        #[test]
+        fn forked_ops_converge((doc, delete_1, delete_2, op1, op2) in doc_and_forked_ops()) {
+            let (d1, d2) = (Delete(delete_1), Delete(delete_2));
+            let seen_1 = deletes_seen(delete_1, &op1);
+            let seen_2 = deletes_seen(delete_2, &op2);
 
-            prop_assert_eq!(doc1, doc2, "\ntransformed_op1 = {:?},\ntransformed_op2 = {:?}\n", transformed_op1, transformed_op2);
+            // Transform the history of each fork against the other one.
+            let (d1_2, d2_1) = transform_pair(&d1, &d2);
+            let op1_2 = transform_from_forks(&op1, seen_1, &d2_1, 0, Left);
+            let d2_1_1 = transform_from_forks(&d2_1, 0, &op1, seen_1, Right);
+            let op2_1 = transform_from_forks(&op2, seen_2, &d1_2, 0, Right);
+            let d1_2_2 = transform_from_forks(&d1_2, 0, &op2, seen_2, Left);
+            let (op1_final, op2_final) = transform_pair(&op1_2, &op2_1);
+
+            let mut doc1 = doc.clone();
+            for op in &[d1, op1, d2_1_1, op2_final] {
+                apply(&mut doc1, op);
+            }
+            let mut doc2 = doc;
+            for op in &[d2, op2, d1_2_2, op1_final] {
+                apply(&mut doc2, op);
+            }
+            prop_assert_eq!(doc1, doc2);
         }
 
         #[test]
-        fn transform_property_2((doc, op1, op2, op3) in doc_and_3_valid_ops()) {
+        fn transform_property_1((doc, op1, op2) in doc_and_two_valid_ops()) {
             let mut doc1 = doc.clone();
             let transformed_op2 = transform(&op2, &op1, Right);
             apply(&mut doc1, &op1);
@@ -171,18 +1102,24 @@ mod tests {
             apply(&mut doc2, &op2);
             apply(&mut doc2, &transformed_op1);
 
-            let op3_transformed_by_1_2 = transform(&transform(&op3, &op1, Right), &transformed_op2, Right);
-            apply(&mut doc1, &op3_transformed_by_1_2);
-            let op3_transformed_by_2_1 = transform(&transform(&op3, &op2, Right), &transformed_op1, Right);
-            apply(&mut doc2, &op3_transformed_by_2_1);
-
             prop_assert_eq!(
-                doc1,
-                doc2,
-                "\nops1 = {:?}\nops2 = {:?}\n",
-                &[op1, transformed_op2, op3_transformed_by_1_2],
-                &[op2, transformed_op1, op3_transformed_by_2_1],
+                &doc1,
+                &doc2,
+                "\n{}\ntransformed_op1 = {:?},\ntransformed_op2 = {:?}\n",
+                format_divergence(&doc1, &doc2),
+                transformed_op1,
+                transformed_op2
             );
         }
+
+        #[test]
+        fn transform_property_2((doc, op1, op2, op3) in doc_and_3_valid_ops()) {
+            check_three_ops(doc, op1, op2, op3)?;
+        }
+
+        #[test]
+        fn clustered_deletes_converge((doc, op1, op2, op3) in doc_and_3_clustered_ops()) {
+            check_three_ops(doc, op1, op2, op3)?;
+        }
     }
 }
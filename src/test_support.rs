@@ -0,0 +1,98 @@
+//! Helpers shared by the tests of several modules.
+
+use crate::composite_op::{apply_with_scratch, compose, transform, Doc, Op, Side::*};
+
+fn render(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).escape_debug().to_string()
+}
+
+/// Describes where two documents diverge: renders both as (lossy, escaped) UTF-8 with a caret
+/// under the first differing byte.
+pub fn format_divergence(doc1: &[u8], doc2: &[u8]) -> String {
+    let index = match doc1.iter().zip(doc2).position(|(a, b)| a != b) {
+        Some(index) => index,
+        None if doc1.len() == doc2.len() => return "documents are equal".to_string(),
+        None => doc1.len().min(doc2.len()),
+    };
+    let caret = " ".repeat(render(&doc1[..index]).chars().count());
+    format!(
+        "documents diverge at byte {}:\n  left: {}\n        {}^\n right: {}\n        {}^",
+        index,
+        render(doc1),
+        caret,
+        render(doc2),
+        caret,
+    )
+}
+
+/// The order in which a peer receives `ops`, shuffled deterministically by `seed`: the same seed
+/// always gives the same order, so failing runs can be reproduced and minimized.
+pub fn shuffled_delivery(mut ops: Vec<Op>, seed: u64) -> Vec<Op> {
+    // xorshift64, which needs a nonzero state.
+    let mut state = if seed == 0 {
+        0x9e37_79b9_7f4a_7c15
+    } else {
+        seed
+    };
+    for i in (1..ops.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        ops.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    ops
+}
+
+/// Applies `ops`, all made concurrently on `doc`, in the order given: each one is transformed
+/// against the ones applied before it, the way a server orders them.
+pub fn replay_delivery(doc: &[u8], ops: &[Op]) -> Doc {
+    let mut doc = doc.to_vec();
+    let mut applied = Op::default();
+    for op in ops {
+        let op = transform(op, &applied, Right);
+        apply_with_scratch(&mut doc, &op, &mut vec![]);
+        applied = compose(&applied, &op);
+    }
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::composite_op::Step::*;
+
+    fn ops() -> Vec<Op> {
+        (0..8)
+            .map(|i| Op(vec![Skip(i % 3), Insert(vec![b'a' + i as u8])]))
+            .collect()
+    }
+
+    #[test]
+    fn test_shuffled_delivery_is_reproducible() {
+        let order = shuffled_delivery(ops(), 42);
+        assert_eq!(shuffled_delivery(ops(), 42), order);
+        assert_ne!(order, ops());
+        assert_ne!(shuffled_delivery(ops(), 43), order);
+
+        // A permutation.
+        assert_eq!(order.len(), 8);
+        assert!(ops().iter().all(|op| order.contains(op)));
+        assert_eq!(
+            replay_delivery(b"xyz", &order),
+            replay_delivery(b"xyz", &shuffled_delivery(ops(), 42))
+        );
+    }
+
+    #[test]
+    fn test_format_divergence() {
+        assert_eq!(
+            format_divergence(b"abcXef", b"abcYef"),
+            "documents diverge at byte 3:\n  left: abcXef\n           ^\n right: abcYef\n           ^"
+        );
+        assert_eq!(
+            format_divergence(b"a\nb", b"a\nbc"),
+            "documents diverge at byte 3:\n  left: a\\nb\n            ^\n right: a\\nbc\n            ^"
+        );
+        assert_eq!(format_divergence(b"ab", b"ab"), "documents are equal");
+    }
+}
@@ -0,0 +1,133 @@
+//! UTF-8 aware ops addressing the document by chars instead of bytes.
+
+use crate::composite_op::{self, apply_with_scratch, Step};
+#[cfg(feature = "proptest")]
+use proptest::prelude::*;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum CharStep {
+    /// Skips `n` chars.
+    Skip(usize),
+    Insert(String),
+    /// Deletes `n` chars.
+    Delete(usize),
+}
+use CharStep::*;
+
+pub type CharOp = Vec<CharStep>;
+
+/// Converts an op with char counts into the equivalent byte op on `doc`.
+///
+/// Panics if the op goes past the end of `doc`.
+pub fn to_byte_op(doc: &str, op: &[CharStep]) -> composite_op::Op {
+    let mut chars = doc.chars();
+    let mut byte_len = |n: usize| -> usize {
+        (0..n)
+            .map(|_| chars.next().expect("op past end of document").len_utf8())
+            .sum()
+    };
+    op.iter()
+        .map(|step| match step {
+            Skip(n) => Step::Skip(byte_len(*n)),
+            Insert(s) => Step::Insert(s.as_bytes().to_vec()),
+            Delete(n) => Step::Delete(byte_len(*n)),
+        })
+        .collect()
+}
+
+/// Applies a char-indexed op to a `String`.
+pub fn apply_str(doc: &mut String, op: &[CharStep]) {
+    let byte_op = to_byte_op(doc, op);
+    let mut bytes = std::mem::replace(doc, String::new()).into_bytes();
+    apply_with_scratch(&mut bytes, &byte_op, &mut vec![]);
+    *doc = String::from_utf8(bytes).expect("char-aligned op keeps the document valid UTF-8");
+}
+
+/// A strategy generating random char ops valid on `text`, for property tests of code built on the
+/// text layer. Inserts are random `char`s and deletes remove whole chars, so applying the ops
+/// keeps any document valid UTF-8.
+#[cfg(feature = "proptest")]
+pub fn valid_char_op_strategy(text: &str) -> impl Strategy<Value = CharOp> {
+    let len = text.chars().count();
+    proptest::collection::vec((0..3u8, 1..5usize, any::<char>()), 0..8).prop_map(move |steps| {
+        let mut remaining = len;
+        let mut op = vec![];
+        for (kind, n, c) in steps {
+            let n = n.min(remaining);
+            match kind {
+                0 if n > 0 => {
+                    remaining -= n;
+                    op.push(Skip(n));
+                }
+                1 if n > 0 => {
+                    remaining -= n;
+                    op.push(Delete(n));
+                }
+                _ => op.push(Insert(c.to_string())),
+            }
+        }
+        op
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_byte_op() {
+        assert_eq!(
+            to_byte_op("aé€😀b", &[Skip(2), Delete(2), Insert("x".to_string())]),
+            vec![Step::Skip(3), Step::Delete(7), Step::Insert(b"x".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_apply_str_insert() {
+        let mut doc = "zażółć".to_string();
+        apply_str(&mut doc, &[Skip(3), Insert("ń€".to_string())]);
+        assert_eq!(doc, "zażń€ółć");
+    }
+
+    #[test]
+    fn test_apply_str_delete() {
+        let mut doc = "a😀b€c".to_string();
+        apply_str(&mut doc, &[Skip(1), Delete(1), Skip(1), Delete(1)]);
+        assert_eq!(doc, "abc");
+    }
+
+    #[test]
+    #[should_panic(expected = "op past end of document")]
+    fn test_apply_str_past_end() {
+        apply_str(&mut "é".to_string(), &[Skip(1), Delete(1)]);
+    }
+
+    #[cfg(feature = "proptest")]
+    mod proptests {
+        use super::*;
+        use crate::composite_op::{transform, Side};
+
+        fn text_and_two_concurrent_ops() -> impl Strategy<Value = (String, CharOp, CharOp)> {
+            any::<String>().prop_flat_map(|text| {
+                (valid_char_op_strategy(&text), valid_char_op_strategy(&text))
+                    .prop_map(move |(op1, op2)| (text.clone(), op1, op2))
+            })
+        }
+
+        proptest! {
+            #[test]
+            fn char_ops_satisfy_tp1((text, op1, op2) in text_and_two_concurrent_ops()) {
+                let op1 = to_byte_op(&text, &op1);
+                let op2 = to_byte_op(&text, &op2);
+                let mut doc1 = text.clone().into_bytes();
+                apply_with_scratch(&mut doc1, &op1, &mut vec![]);
+                apply_with_scratch(&mut doc1, &transform(&op2, &op1, Side::Right), &mut vec![]);
+                let mut doc2 = text.into_bytes();
+                apply_with_scratch(&mut doc2, &op2, &mut vec![]);
+                apply_with_scratch(&mut doc2, &transform(&op1, &op2, Side::Left), &mut vec![]);
+                prop_assert_eq!(&doc1, &doc2);
+                prop_assert!(String::from_utf8(doc1).is_ok());
+            }
+        }
+    }
+}
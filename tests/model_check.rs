@@ -0,0 +1,142 @@
+//! Exhaustive convergence check of the client/server protocol for three concurrent edits.
+//!
+//! The outcome of a run only depends on the order in which the server receives the edits and,
+//! for each client, on how many broadcasts it had applied before making its edit. All other
+//! interleavings (e.g. of deliveries to different clients) commute, so enumerating these two
+//! covers every possible delivery order.
+//!
+//! Note that this relies on the server ordering the ops: the composite `transform` only
+//! satisfies TP1, so peers applying each other's ops in arbitrary orders without a server can
+//! diverge (e.g. on "abc", inserting at 1, inserting at 0 and deleting at 0).
+
+use ot::client::Client;
+use ot::composite_op::{Doc, Step, Step::*};
+use ot::envelope::Envelope;
+use ot::server::Server;
+
+const NUM_CLIENTS: usize = 3;
+
+/// An edit a client makes, relative to its document at the time.
+#[derive(Clone, Copy, Debug)]
+enum Edit {
+    Insert(usize),
+    Delete(usize),
+}
+
+const EDITS: &[Edit] = &[
+    Edit::Insert(0),
+    Edit::Insert(1),
+    Edit::Insert(2),
+    Edit::Delete(0),
+    Edit::Delete(1),
+];
+
+fn make_op(edit: Edit, doc: &[u8], client: usize) -> Vec<Step> {
+    match edit {
+        Edit::Delete(index) if !doc.is_empty() => vec![Skip(index.min(doc.len() - 1)), Delete(1)],
+        Edit::Insert(index) | Edit::Delete(index) => vec![
+            Skip(index.min(doc.len())),
+            Insert(vec![b'x' + client as u8]),
+        ],
+    }
+}
+
+fn deliver(client: &mut Client, broadcast: Envelope) {
+    // With one edit per client nothing is buffered, so there's nothing to send after the ack.
+    assert_eq!(client.apply_server(broadcast), None);
+}
+
+/// Runs one schedule: the server receives the edits in `order`, and client `i` applies
+/// `seen[i]` broadcasts before making its edit.
+fn run(edits: &[Edit], order: &[usize], seen: &[usize]) {
+    let initial: Doc = b"abc".to_vec();
+    let mut server = Server::new(initial.clone());
+    let mut clients: Vec<Client> = (0..NUM_CLIENTS)
+        .map(|i| Client::new(i as u64, initial.clone(), 0))
+        .collect();
+    let mut inboxes: Vec<Vec<Envelope>> = (0..NUM_CLIENTS).map(|_| vec![]).collect();
+    let mut delivered = vec![0; NUM_CLIENTS];
+
+    for &i in order {
+        for message in inboxes[i].drain(..seen[i] - delivered[i]) {
+            deliver(&mut clients[i], message);
+        }
+        delivered[i] = seen[i];
+
+        let op = make_op(edits[i], clients[i].document(), i);
+        let sent = clients[i]
+            .apply_local(op)
+            .expect("nothing else is in flight");
+        let broadcast = server.receive_envelope(clients[i].version(), sent).unwrap();
+        for inbox in &mut inboxes {
+            inbox.push(broadcast.clone());
+        }
+    }
+
+    for (client, inbox) in clients.iter_mut().zip(&mut inboxes) {
+        for message in inbox.drain(..) {
+            deliver(client, message);
+        }
+        assert_eq!(
+            client.document(),
+            server.document(),
+            "\nedits = {:?}\norder = {:?}\nseen = {:?}",
+            edits,
+            order,
+            seen
+        );
+    }
+}
+
+fn permutations(n: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![vec![]];
+    }
+    let mut result = vec![];
+    for perm in permutations(n - 1) {
+        for i in 0..n {
+            let mut perm = perm.clone();
+            perm.insert(i, n - 1);
+            result.push(perm);
+        }
+    }
+    result
+}
+
+/// All ways to pick `seen[i]` so that a client only sees edits the server received before its
+/// own.
+fn seen_counts(order: &[usize]) -> Vec<Vec<usize>> {
+    let mut result = vec![vec![0; NUM_CLIENTS]];
+    for (position, &i) in order.iter().enumerate() {
+        result = result
+            .into_iter()
+            .flat_map(|seen| {
+                (0..=position).map(move |k| {
+                    let mut seen = seen.clone();
+                    seen[i] = k;
+                    seen
+                })
+            })
+            .collect();
+    }
+    result
+}
+
+#[test]
+fn all_schedules_of_three_edits_converge() {
+    let orders = permutations(NUM_CLIENTS);
+    let mut runs = 0;
+    for &a in EDITS {
+        for &b in EDITS {
+            for &c in EDITS {
+                for order in &orders {
+                    for seen in seen_counts(order) {
+                        run(&[a, b, c], order, &seen);
+                        runs += 1;
+                    }
+                }
+            }
+        }
+    }
+    assert_eq!(runs, EDITS.len().pow(3) * 36);
+}
@@ -0,0 +1,26 @@
+use ot::prelude::*;
+
+#[test]
+fn prelude_brings_the_composite_op_api_into_scope() {
+    let mut doc: Doc = b"abc".to_vec();
+    let op1 = Op(vec![Skip(1), Insert(b"x".to_vec())]);
+    let op2: Op = vec![Delete(1)].into();
+    let side: Side = Left;
+    let op1_prime = transform(&op1, &op2, side);
+    let op2_prime = transform(&op2, &op1, Right);
+    apply(&mut doc, &compose(&op2, &op1_prime));
+    assert_eq!(doc, b"xbc");
+
+    let mut doc: Doc = b"abc".to_vec();
+    try_apply(&mut doc, &normalize(&compose(&op1, &op2_prime))).unwrap();
+    assert_eq!(doc, b"xbc");
+    let _: Option<ApplyError> = None;
+    let _: Step = Skip(0);
+
+    let mut server = Server::new(doc.clone());
+    let mut client = Client::new(0, doc, 0);
+    let sent: Envelope = client.apply_local(vec![Delete(1)]).unwrap();
+    let broadcast = server.receive_envelope(client.version(), sent).unwrap();
+    assert_eq!(client.apply_server(broadcast), None);
+    assert_eq!(client.document(), server.document());
+}